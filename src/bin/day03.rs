@@ -4,118 +4,170 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-const MAX_NUM_LENGTH: i32 = 3;
-
 #[derive(PartialEq)]
 enum Data {
     Symbol(char),
-    Number(i64),
+    Number { value: i64, length: i32 },
 }
 
 type Schematic = HashMap<(i32, i32), Data>;
 
-fn num_length(mut num: i64) -> i32 {
-    let mut ret = 0;
-    while num > 0 {
-        num /= 10;
-        ret += 1
-    }
-    ret
+// Digits are ASCII, so the byte range of a number is also its width in columns
+fn insert_number(
+    ret: &mut Schematic,
+    line: &str,
+    row: usize,
+    (col, start): (usize, usize),
+    end: usize,
+) -> Result<(), Error> {
+    ret.insert(
+        (row as i32, col as i32),
+        Number {
+            value: line[start..end].parse()?,
+            length: (end - start) as i32,
+        },
+    );
+    Ok(())
 }
 
 fn extract_data(lines: &[&str]) -> Result<Schematic, Error> {
     let mut ret = HashMap::new();
     for (row, line) in lines.iter().enumerate() {
-        let mut number: Option<i64> = None;
-        for (col, c) in line.chars().enumerate() {
+        // Column and byte offset of the current number's first digit
+        let mut number_start: Option<(usize, usize)> = None;
+        for (col, (byte, c)) in line.char_indices().enumerate() {
             if c.is_ascii_digit() {
-                number = Some(
-                    number.unwrap_or(0) * 10
-                        + c.to_digit(10).ok_or(Error::msg("Not a digit"))? as i64,
-                );
+                number_start.get_or_insert((col, byte));
             } else {
-                if let Some(finished_number) = number {
-                    ret.insert(
-                        (row as i32, col as i32 - num_length(finished_number)),
-                        Number(finished_number),
-                    );
-                    number = None;
+                if let Some(start) = number_start {
+                    insert_number(&mut ret, line, row, start, byte)?;
+                    number_start = None;
                 }
                 if c != '.' {
                     ret.insert((row as i32, col as i32), Symbol(c));
                 }
             }
         }
-        if let Some(finished_number) = number {
-            ret.insert(
-                (row as i32, line.len() as i32 - num_length(finished_number)),
-                Number(finished_number),
-            );
+        if let Some(start) = number_start {
+            insert_number(&mut ret, line, row, start, line.len())?;
         }
     }
     Ok(ret)
 }
 
-fn contains_symbol(data: &Schematic, pos: (i32, i32)) -> bool {
-    matches!(data.get(&pos), Some(Symbol(_)))
+fn neighbours(pos: (i32, i32), length: i32) -> impl Iterator<Item = (i32, i32)> {
+    (pos.0 - 1..pos.0 + 2)
+        .flat_map(move |row| (pos.1 - 1..pos.1 + length + 1).map(move |col| (row, col)))
 }
 
-fn touching_symbol(data: &Schematic, pos: (i32, i32), number: i64) -> bool {
-    let right_pos = (pos.0, pos.1 + num_length(number) - 1);
-    for dr in -1..2 {
-        for dc in -1..2 {
-            if contains_symbol(data, (pos.0 + dr, pos.1 + dc))
-                || contains_symbol(data, (right_pos.0 + dr, right_pos.1 + dc))
-            {
-                return true;
-            }
-        }
-    }
-    false
+struct SchematicGraph {
+    symbols: Vec<((i32, i32), char)>,
+    numbers: Vec<((i32, i32), i64)>,
+    numbers_by_symbol: Vec<Vec<usize>>,
+    symbols_by_number: Vec<Vec<usize>>,
+    symbol_index: HashMap<(i32, i32), usize>,
 }
 
-fn get_gear_ratio(data: &Schematic, pos: (i32, i32)) -> Option<i64> {
-    if data.get(&pos) != Some(&Symbol('*')) {
-        return None;
-    }
+impl SchematicGraph {
+    fn from_schematic(data: &Schematic) -> Self {
+        let mut symbols: Vec<_> = data
+            .iter()
+            .filter_map(|(pos, d)| match d {
+                Symbol(c) => Some((*pos, *c)),
+                _ => None,
+            })
+            .collect();
+        symbols.sort();
+        let symbol_index: HashMap<(i32, i32), usize> = symbols
+            .iter()
+            .enumerate()
+            .map(|(i, (pos, _))| (*pos, i))
+            .collect();
+
+        let mut number_spans: Vec<_> = data
+            .iter()
+            .filter_map(|(pos, d)| match d {
+                Number { value, length } => Some((*pos, *value, *length)),
+                _ => None,
+            })
+            .collect();
+        number_spans.sort();
 
-    let mut numbers = vec![];
-    for row in pos.0 - 1..pos.0 + 2 {
-        for col in pos.1 - MAX_NUM_LENGTH..pos.1 + 2 {
-            if let Some(Number(number)) = data.get(&(row, col)) {
-                if col + num_length(*number) >= pos.1 {
-                    numbers.push(*number);
+        let mut numbers_by_symbol = vec![vec![]; symbols.len()];
+        let mut symbols_by_number = vec![vec![]; number_spans.len()];
+        for (number, (pos, _, length)) in number_spans.iter().enumerate() {
+            for n in neighbours(*pos, *length) {
+                if let Some(&symbol) = symbol_index.get(&n) {
+                    numbers_by_symbol[symbol].push(number);
+                    symbols_by_number[number].push(symbol);
                 }
             }
         }
+
+        Self {
+            symbols,
+            numbers: number_spans
+                .into_iter()
+                .map(|(pos, value, _)| (pos, value))
+                .collect(),
+            numbers_by_symbol,
+            symbols_by_number,
+            symbol_index,
+        }
     }
 
-    if numbers.len() == 2 {
-        Some(numbers[0] * numbers[1])
-    } else {
-        None
+    fn symbol_at(&self, pos: (i32, i32)) -> Option<usize> {
+        self.symbol_index.get(&pos).copied()
+    }
+
+    fn numbers_adjacent_to(&self, symbol: usize) -> impl Iterator<Item = i64> + '_ {
+        self.numbers_by_symbol[symbol]
+            .iter()
+            .map(|&n| self.numbers[n].1)
+    }
+
+    fn symbols_adjacent_to(&self, number: usize) -> impl Iterator<Item = ((i32, i32), char)> + '_ {
+        self.symbols_by_number[number]
+            .iter()
+            .map(|&s| self.symbols[s])
+    }
+
+    fn symbols_with_arity(&self, kind: char, arity: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.symbols.len())
+            .filter(move |&s| self.symbols[s].1 == kind && self.numbers_by_symbol[s].len() == arity)
+    }
+
+    fn orphan_numbers(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..self.numbers.len())
+            .filter(|&n| self.symbols_adjacent_to(n).next().is_none())
+            .map(|n| self.numbers[n].1)
     }
 }
 
-fn solve(data: &Schematic) -> i64 {
-    data.iter()
-        .filter_map(|data_point| {
-            if let Number(number) = *data_point.1 {
-                if touching_symbol(data, *data_point.0, number) {
-                    Some(number)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
+struct GearRule {
+    symbol: char,
+    arity: usize,
+}
+
+impl GearRule {
+    fn from_str(string: &str) -> Result<Self, Error> {
+        let mut chars = string.chars();
+        let symbol = chars.next().ok_or(Error::msg("Gear symbol expected"))?;
+        Ok(Self {
+            symbol,
+            arity: chars.as_str().parse()?,
         })
-        .sum()
+    }
 }
 
-fn solve2(data: &Schematic) -> i64 {
-    data.iter()
-        .filter_map(|data_point| get_gear_ratio(data, *data_point.0))
+fn solve(graph: &SchematicGraph) -> i64 {
+    graph.numbers.iter().map(|(_, n)| n).sum::<i64>() - graph.orphan_numbers().sum::<i64>()
+}
+
+fn solve2(graph: &SchematicGraph, rule: &GearRule) -> i64 {
+    graph
+        .symbols_with_arity(rule.symbol, rule.arity)
+        .map(|s| graph.numbers_adjacent_to(s).product::<i64>())
         .sum()
 }
 
@@ -123,9 +175,95 @@ fn main() -> Result<(), Error> {
     let file_name = Path::new("data/input03.txt");
     let file_data = fs::read_to_string(file_name)?;
     let lines: Vec<_> = file_data.lines().collect();
-    let data = extract_data(&lines)?;
-    println!("{}", solve(&data));
-    println!("{}", solve2(&data));
+    let graph = SchematicGraph::from_schematic(&extract_data(&lines)?);
+    println!("{}", solve(&graph));
+    println!("{}", solve2(&graph, &GearRule::from_str("*2")?));
+    for arg in std::env::args().skip(1) {
+        // "row,col" lists the numbers touching the symbol there, anything else is a gear rule
+        if let Some((row, col)) = arg.split_once(',') {
+            let symbol = graph
+                .symbol_at((row.parse()?, col.parse()?))
+                .ok_or(Error::msg(format!("No symbol at {}", arg)))?;
+            let numbers: Vec<_> = graph.numbers_adjacent_to(symbol).collect();
+            println!("{} {}: {:?}", arg, graph.symbols[symbol].1, numbers);
+        } else {
+            println!("{}: {}", arg, solve2(&graph, &GearRule::from_str(&arg)?));
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [&str; 10] = [
+        "467..114..",
+        "...*......",
+        "..35..633.",
+        "......#...",
+        "617*......",
+        ".....+.58.",
+        "..592.....",
+        "......755.",
+        "...$.*....",
+        ".664.598..",
+    ];
+
+    #[test]
+    fn test_example() {
+        let graph = SchematicGraph::from_schematic(&extract_data(&EXAMPLE).unwrap());
+        assert_eq!(solve(&graph), 4361);
+        assert_eq!(solve2(&graph, &GearRule::from_str("*2").unwrap()), 467835);
+        assert_eq!(graph.orphan_numbers().collect::<Vec<_>>(), vec![114, 58]);
+        assert_eq!(graph.symbols_with_arity('*', 1).count(), 1);
+    }
+
+    #[test]
+    fn test_long_numbers_and_zero() {
+        let data = extract_data(&["1234*....0", "....5678#."]).unwrap();
+        assert!(
+            data.get(&(0, 9))
+                == Some(&Number {
+                    value: 0,
+                    length: 1
+                })
+        );
+        let graph = SchematicGraph::from_schematic(&data);
+        assert_eq!(solve(&graph), 1234 + 5678);
+        assert_eq!(
+            solve2(&graph, &GearRule::from_str("*2").unwrap()),
+            1234 * 5678
+        );
+    }
+
+    #[test]
+    fn test_positions() {
+        let graph = SchematicGraph::from_schematic(&extract_data(&["*12*", ".*.."]).unwrap());
+        let symbols: Vec<_> = graph.symbols_adjacent_to(0).collect();
+        assert_eq!(symbols, vec![((0, 0), '*'), ((0, 3), '*'), ((1, 1), '*')]);
+        let right = graph.symbol_at((0, 3)).unwrap();
+        assert_eq!(
+            graph.numbers_adjacent_to(right).collect::<Vec<_>>(),
+            vec![12]
+        );
+        assert_eq!(graph.symbol_at((1, 0)), None);
+    }
+
+    #[test]
+    fn test_non_ascii_columns() {
+        let data = extract_data(&["é.12", "€..*"]).unwrap();
+        assert!(data.get(&(0, 0)) == Some(&Symbol('é')));
+        assert!(
+            data.get(&(0, 2))
+                == Some(&Number {
+                    value: 12,
+                    length: 2
+                })
+        );
+        assert!(data.get(&(1, 3)) == Some(&Symbol('*')));
+        let graph = SchematicGraph::from_schematic(&data);
+        assert_eq!(solve(&graph), 12);
+    }
+}
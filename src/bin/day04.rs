@@ -25,32 +25,98 @@ impl Card {
             .sum()
     }
 
-    fn get_points(&self) -> i64 {
-        let num_matches = self.get_num_matches();
+    fn get_points(&self, scoring: &Scoring) -> Result<i64, Error> {
+        scoring.points(self.get_num_matches())
+    }
+}
 
-        if num_matches == 0 {
-            0
-        } else {
-            2_i64.pow(num_matches - 1)
-        }
+enum Scoring {
+    Doubling,
+    Linear,
+    Table(Vec<i64>),
+}
+
+impl Scoring {
+    fn from_str(string: &str) -> Result<Self, Error> {
+        Ok(match string {
+            "doubling" => Scoring::Doubling,
+            "linear" => Scoring::Linear,
+            _ => Scoring::Table(
+                string
+                    .split(',')
+                    .map(|n| n.trim().parse())
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    fn points(&self, num_matches: u32) -> Result<i64, Error> {
+        Ok(match self {
+            Scoring::Doubling if num_matches == 0 => 0,
+            Scoring::Doubling => 2_i64
+                .checked_pow(num_matches - 1)
+                .ok_or(Error::msg(format!(
+                    "Score for {} matches overflows",
+                    num_matches
+                )))?,
+            Scoring::Linear => num_matches as i64,
+            Scoring::Table(table) => *table
+                .get(num_matches as usize)
+                .ok_or(Error::msg(format!("No score for {} matches", num_matches)))?,
+        })
     }
 }
 
-fn solve(cards: &[Card]) -> i64 {
-    cards.iter().map(|c| c.get_points()).sum()
+struct Cascade {
+    copies: Vec<usize>,
+    sources: Vec<Vec<(usize, usize)>>,
 }
 
-fn solve2(cards: &[Card]) -> usize {
-    let mut num_cards = vec![1_usize; cards.len()];
+impl Cascade {
+    fn from_cards(cards: &[Card]) -> Result<Self, Error> {
+        let mut copies = vec![1_usize; cards.len()];
+        let mut sources = vec![vec![]; cards.len()];
 
-    for (card_index, card) in cards.iter().enumerate().rev().skip(1) {
-        let num_matches = card.get_num_matches() as usize;
-        num_cards[card_index] += num_cards[card_index + 1..card_index + 1 + num_matches]
-            .iter()
-            .sum::<usize>();
+        for (card_index, card) in cards.iter().enumerate() {
+            let num_matches = card.get_num_matches() as usize;
+            if card_index + num_matches >= cards.len() {
+                return Err(Error::msg(format!(
+                    "Card {} wins {} cards past the end of the table",
+                    card_index + 1,
+                    card_index + num_matches + 1 - cards.len()
+                )));
+            }
+            for won_index in card_index + 1..card_index + 1 + num_matches {
+                copies[won_index] += copies[card_index];
+                sources[won_index].push((card_index, copies[card_index]));
+            }
+        }
+
+        Ok(Self { copies, sources })
     }
 
-    num_cards.iter().sum()
+    fn print(&self) {
+        for (card_index, copies) in self.copies.iter().enumerate() {
+            let sources: Vec<_> = self.sources[card_index]
+                .iter()
+                .map(|(source, n)| format!("{}x{}", n, source + 1))
+                .collect();
+            println!(
+                "Card {}: {} copies <- [{}]",
+                card_index + 1,
+                copies,
+                sources.join(", ")
+            );
+        }
+    }
+}
+
+fn solve(cards: &[Card], scoring: &Scoring) -> Result<i64, Error> {
+    cards.iter().map(|c| c.get_points(scoring)).sum()
+}
+
+fn solve2(cascade: &Cascade) -> usize {
+    cascade.copies.iter().sum()
 }
 
 fn main() -> Result<(), Error> {
@@ -58,7 +124,60 @@ fn main() -> Result<(), Error> {
         .lines()
         .map(Card::from_str)
         .collect::<Result<Vec<Card>, _>>()?;
-    println!("{}", solve(&cards));
-    println!("{}", solve2(&cards));
+    let cascade = Cascade::from_cards(&cards)?;
+    println!("{}", solve(&cards, &Scoring::Doubling)?);
+    println!("{}", solve2(&cascade));
+    for arg in std::env::args().skip(1) {
+        if arg == "trace" {
+            cascade.print();
+        } else {
+            println!("{}: {}", arg, solve(&cards, &Scoring::from_str(&arg)?)?);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [&str; 6] = [
+        "Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53",
+        "Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19",
+        "Card 3:  1 21 53 59 44 | 69 82 63 72 16 21 14  1",
+        "Card 4: 41 92 73 84 69 | 59 84 76 51 58  5 54 83",
+        "Card 5: 87 83 26 28 32 | 88 30 70 12 93 22 82 36",
+        "Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11",
+    ];
+
+    fn parse(lines: &[&str]) -> Vec<Card> {
+        lines.iter().map(|l| Card::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_example() {
+        let cards = parse(&EXAMPLE);
+        assert_eq!(solve(&cards, &Scoring::Doubling).unwrap(), 13);
+        assert_eq!(solve(&cards, &Scoring::Linear).unwrap(), 4 + 2 + 2 + 1);
+        let cascade = Cascade::from_cards(&cards).unwrap();
+        assert_eq!(solve2(&cascade), 30);
+        assert_eq!(cascade.copies, vec![1, 2, 4, 8, 14, 1]);
+    }
+
+    #[test]
+    fn test_table_scoring() {
+        let cards = parse(&EXAMPLE);
+        let table = Scoring::from_str("0, 10, 20, 30, 40").unwrap();
+        assert_eq!(solve(&cards, &table).unwrap(), 40 + 20 + 20 + 10);
+        let short_table = Scoring::from_str("0,1,2").unwrap();
+        assert!(solve(&cards, &short_table).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let cards = parse(&["Card 1: 1 2 | 3 4", "Card 2: 1 2 | 1 5"]);
+        assert!(Cascade::from_cards(&cards).is_err());
+        assert!(Scoring::Doubling.points(63).is_ok());
+        assert!(Scoring::Doubling.points(64).is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use anyhow::Error;
 use aoc2023::parse_numbers;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 
@@ -9,17 +12,18 @@ struct Range {
     length: i64,
 }
 
+impl Range {
+    fn intersects(&self, other: &Range) -> bool {
+        self.start < other.start + other.length && other.start < self.start + self.length
+    }
+}
+
 struct MapRange {
     dest_start: i64,
     source_start: i64,
     length: i64,
 }
 
-struct MappedRange {
-    unmapped: Vec<Range>,
-    mapped: Option<Range>,
-}
-
 impl MapRange {
     fn from_string(line: &str) -> Option<Self> {
         let numbers = parse_numbers(line).ok()?;
@@ -34,95 +38,272 @@ impl MapRange {
         }
     }
 
-    fn apply(&self, value: i64) -> Option<i64> {
-        if self.source_start <= value && value - self.source_start < self.length {
-            Some(self.dest_start + (value - self.source_start))
+    fn inverse(&self) -> Self {
+        Self {
+            dest_start: self.source_start,
+            source_start: self.dest_start,
+            length: self.length,
+        }
+    }
+
+    fn apply_range(&self, range: &Range) -> Option<Range> {
+        let start = max(range.start, self.source_start);
+        let end = min(range.start + range.length, self.source_start + self.length);
+        if start < end {
+            Some(Range {
+                start: self.dest_start + (start - self.source_start),
+                length: end - start,
+            })
         } else {
             None
         }
     }
+}
 
-    fn apply_range(&self, range: &Range) -> MappedRange {
-        let range_end = range.start + range.length;
-        let source_end = self.source_start + self.length;
-        let dest_end = self.dest_start + self.length;
+fn merge_ranges(mut ranges: Vec<Range>) -> Vec<Range> {
+    ranges.sort_by_key(|r| r.start);
+    let mut ret: Vec<Range> = vec![];
+    for range in ranges {
+        match ret.last_mut() {
+            Some(last) if last.start + last.length >= range.start => {
+                last.length = max(last.length, range.start + range.length - last.start)
+            }
+            _ => ret.push(range),
+        }
+    }
+    ret
+}
+
+// Piecewise maps cover [0, DOMAIN_END); seeds and map ranges are checked against it on parsing
+const DOMAIN_END: i64 = i64::MAX / 2;
+
+fn in_domain(start: i64, length: i64) -> bool {
+    start >= 0
+        && length >= 0
+        && start
+            .checked_add(length)
+            .is_some_and(|end| end <= DOMAIN_END)
+}
+
+#[derive(Clone, Debug)]
+struct Segment {
+    start: i64,
+    end: i64,
+    offset: i64,
+}
+
+impl Segment {
+    fn as_map_range(&self) -> MapRange {
+        MapRange {
+            dest_start: self.start + self.offset,
+            source_start: self.start,
+            length: self.end - self.start,
+        }
+    }
+}
 
-        if range_end <= self.source_start || range.start >= source_end {
-            MappedRange {
-                mapped: None,
-                unmapped: vec![range.clone()],
+struct PiecewiseMap {
+    segments: Vec<Segment>,
+}
+
+impl PiecewiseMap {
+    fn identity() -> Self {
+        Self {
+            segments: vec![Segment {
+                start: 0,
+                end: DOMAIN_END,
+                offset: 0,
+            }],
+        }
+    }
+
+    fn overwrite(&mut self, start: i64, end: i64, offset: i64) {
+        let mut segments = vec![Segment { start, end, offset }];
+        for segment in &self.segments {
+            if segment.start < start {
+                segments.push(Segment {
+                    end: min(segment.end, start),
+                    ..*segment
+                });
             }
-        } else {
-            let mut unmapped = vec![];
-            if range.start < self.source_start {
-                unmapped.push(Range {
-                    start: range.start,
-                    length: self.source_start - range.start,
+            if segment.end > end {
+                segments.push(Segment {
+                    start: max(segment.start, end),
+                    ..*segment
                 });
             }
-            if range_end > source_end {
-                unmapped.push(Range {
-                    start: source_end,
-                    length: range_end - source_end,
-                })
+        }
+        segments.sort_by_key(|s| s.start);
+        self.segments = segments;
+    }
+
+    fn merged(self) -> Self {
+        let mut segments: Vec<Segment> = vec![];
+        for segment in self.segments {
+            match segments.last_mut() {
+                Some(last) if last.offset == segment.offset && last.end == segment.start => {
+                    last.end = segment.end
+                }
+                _ => segments.push(segment),
             }
-            let mapped_start = self.apply(range.start).unwrap_or(self.dest_start);
-            let mapped_end = self.apply(range_end).unwrap_or(dest_end);
-            let mapped = Some(Range {
-                start: mapped_start,
-                length: mapped_end - mapped_start,
-            });
-            MappedRange { mapped, unmapped }
         }
+        Self { segments }
+    }
+
+    fn overlapping(&self, start: i64, end: i64) -> impl Iterator<Item = &Segment> {
+        let first = self.segments.partition_point(|s| s.end <= start);
+        self.segments[first..]
+            .iter()
+            .take_while(move |s| s.start < end)
+    }
+
+    fn apply(&self, value: i64) -> i64 {
+        value
+            + self
+                .overlapping(value, value + 1)
+                .next()
+                .map_or(0, |s| s.offset)
+    }
+
+    fn apply_range(&self, range: &Range) -> Vec<Range> {
+        let range_end = range.start + range.length;
+        self.overlapping(range.start, range_end)
+            .map(|s| {
+                let start = max(s.start, range.start);
+                Range {
+                    start: start + s.offset,
+                    length: min(s.end, range_end) - start,
+                }
+            })
+            .collect()
+    }
+
+    fn preimage(&self, range: &Range) -> Vec<Range> {
+        merge_ranges(
+            self.segments
+                .iter()
+                .filter_map(|s| s.as_map_range().inverse().apply_range(range))
+                .collect(),
+        )
+    }
+
+    fn then(&self, other: &PiecewiseMap) -> PiecewiseMap {
+        let mut segments = vec![];
+        for segment in &self.segments {
+            let image_start = segment.start + segment.offset;
+            let image_end = segment.end + segment.offset;
+            for next in other.overlapping(image_start, image_end) {
+                segments.push(Segment {
+                    start: max(segment.start, next.start - segment.offset),
+                    end: min(segment.end, next.end - segment.offset),
+                    offset: segment.offset + next.offset,
+                });
+            }
+        }
+        Self { segments }.merged()
+    }
+}
+
+impl fmt::Display for PiecewiseMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for s in &self.segments {
+            writeln!(
+                f,
+                "[{}, {}) -> [{}, {})",
+                s.start,
+                s.end,
+                s.start + s.offset,
+                s.end + s.offset
+            )?;
+        }
+        Ok(())
     }
 }
 
 struct Mapping {
+    source: String,
+    destination: String,
     ranges: Vec<MapRange>,
 }
 
+enum RangeIssue {
+    Overlap(i64, i64),
+    Gap(i64, i64),
+}
+
+impl fmt::Display for RangeIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeIssue::Overlap(start, end) => {
+                write!(f, "overlapping sources [{}, {})", start, end)
+            }
+            RangeIssue::Gap(start, end) => write!(f, "gap in sources [{}, {})", start, end),
+        }
+    }
+}
+
 impl Mapping {
-    fn from_iter(it: &mut dyn Iterator<Item = &&str>) -> Mapping {
+    fn from_iter(it: &mut dyn Iterator<Item = &&str>) -> Result<Mapping, Error> {
+        let header = it.next().ok_or(Error::msg("Map header expected"))?;
+        let (source, destination) = header
+            .strip_suffix(" map:")
+            .and_then(|h| h.split_once("-to-"))
+            .ok_or(Error::msg(format!("Invalid map header: {}", header)))?;
         let mut ranges = vec![];
-        it.next();
         for line in it.take_while(|l| !l.is_empty()) {
-            ranges.push(MapRange::from_string(line).unwrap());
+            let range = MapRange::from_string(line)
+                .filter(|r| {
+                    in_domain(r.source_start, r.length) && in_domain(r.dest_start, r.length)
+                })
+                .ok_or(Error::msg(format!("Invalid range in {}: {}", header, line)))?;
+            ranges.push(range);
         }
 
-        Self { ranges }
+        Ok(Self {
+            source: String::from(source),
+            destination: String::from(destination),
+            ranges,
+        })
     }
 
-    fn apply(&self, number: i64) -> i64 {
-        let ret = self
+    fn range_issues(&self) -> Vec<RangeIssue> {
+        let mut sources: Vec<_> = self
             .ranges
             .iter()
-            .filter_map(|range| range.apply(number))
-            .next()
-            .unwrap_or(number);
-        ret
-    }
+            .map(|r| (r.source_start, r.source_start + r.length))
+            .collect();
+        sources.sort();
 
-    fn apply_range(&self, range: &Range) -> Vec<Range> {
         let mut ret = vec![];
-        let mut to_map = vec![range.clone()];
-        for map_range in &self.ranges {
-            let mut to_map_next: Vec<Range> = vec![];
-            for range_to_map in &to_map {
-                let mut half_mapped = map_range.apply_range(range_to_map);
-                to_map_next.append(&mut half_mapped.unmapped);
-                if let Some(mapped) = half_mapped.mapped {
-                    ret.push(mapped);
+        let mut covered_end: Option<i64> = None;
+        for (start, end) in sources {
+            match covered_end {
+                Some(covered) if start < covered => {
+                    ret.push(RangeIssue::Overlap(start, min(end, covered)))
                 }
+                Some(covered) if start > covered => ret.push(RangeIssue::Gap(covered, start)),
+                _ => {}
             }
-            to_map = to_map_next;
+            covered_end = Some(max(covered_end.unwrap_or(end), end));
         }
-
-        ret.append(&mut to_map);
         ret
     }
 
-    fn apply_ranges(&self, ranges: &[Range]) -> Vec<Range> {
-        ranges.iter().flat_map(|r| self.apply_range(r)).collect()
+    fn to_piecewise(&self) -> PiecewiseMap {
+        let mut ret = PiecewiseMap::identity();
+        // Earlier ranges take precedence, so they are written last
+        for range in self.ranges.iter().rev() {
+            ret.overwrite(
+                range.source_start,
+                range.source_start + range.length,
+                range.dest_start - range.source_start,
+            );
+        }
+        ret.merged()
+    }
+
+    fn inverse_apply_range(&self, range: &Range) -> Vec<Range> {
+        self.to_piecewise().preimage(range)
     }
 }
 struct Almanac {
@@ -139,11 +320,43 @@ impl Almanac {
                 .strip_prefix("seeds: ")
                 .ok_or(Error::msg("Seeds expected"))?,
         )?;
+        let seed_range_in_domain = |pair: &[i64]| match pair {
+            [start, length] => in_domain(*start, *length),
+            _ => true,
+        };
+        if !seeds.iter().all(|&seed| in_domain(seed, 1))
+            || !seeds.chunks(2).all(seed_range_in_domain)
+        {
+            return Err(Error::msg(format!(
+                "Seeds and seed ranges must lie in [0, {})",
+                DOMAIN_END
+            )));
+        }
 
-        let mut maps = vec![];
+        let mut maps_by_source = HashMap::new();
         let mut line_iter = lines.iter().skip(2).peekable();
         while line_iter.peek().is_some() {
-            maps.push(Mapping::from_iter(&mut line_iter));
+            let mapping = Mapping::from_iter(&mut line_iter)?;
+            if maps_by_source.contains_key(&mapping.source) {
+                return Err(Error::msg(format!("Duplicate map from {}", mapping.source)));
+            }
+            maps_by_source.insert(mapping.source.clone(), mapping);
+        }
+
+        let mut maps = vec![];
+        let mut category = String::from("seed");
+        while category != "location" {
+            let mapping = maps_by_source
+                .remove(&category)
+                .ok_or(Error::msg(format!("No map from {}", category)))?;
+            category = mapping.destination.clone();
+            maps.push(mapping);
+        }
+        if let Some(unused) = maps_by_source.keys().next() {
+            return Err(Error::msg(format!(
+                "Map from {} is not part of the seed-to-location chain",
+                unused
+            )));
         }
         Ok(Self { seeds, maps })
     }
@@ -158,42 +371,267 @@ impl Almanac {
         }
         ret
     }
+
+    fn compose(
+        &self,
+        source: &str,
+        destination: &str,
+    ) -> Result<(PiecewiseMap, Vec<usize>), Error> {
+        let first = self
+            .maps
+            .iter()
+            .position(|m| m.source == source)
+            .ok_or(Error::msg(format!("Unknown source category {}", source)))?;
+        let mut composed = PiecewiseMap::identity();
+        let mut num_segments = vec![];
+        for mapping in &self.maps[first..] {
+            composed = composed.then(&mapping.to_piecewise());
+            num_segments.push(composed.segments.len());
+            if mapping.destination == destination {
+                return Ok((composed, num_segments));
+            }
+        }
+        Err(Error::msg(format!(
+            "No conversion from {} to {}",
+            source, destination
+        )))
+    }
+
+    fn inverse_apply_range(&self, range: &Range) -> Vec<Range> {
+        self.maps
+            .iter()
+            .rev()
+            .fold(vec![range.clone()], |ranges, mapping| {
+                merge_ranges(
+                    ranges
+                        .iter()
+                        .flat_map(|r| mapping.inverse_apply_range(r))
+                        .collect(),
+                )
+            })
+    }
+
+    fn seeds_with_location_at_most(&self, location: i64) -> Vec<Range> {
+        self.inverse_apply_range(&Range {
+            start: 0,
+            length: location + 1,
+        })
+    }
 }
 
-fn solve(almanac: &Almanac) -> i64 {
+fn solve(almanac: &Almanac, composed: &PiecewiseMap) -> i64 {
     almanac
         .seeds
         .iter()
-        .map(|seed| {
-            almanac
-                .maps
-                .iter()
-                .fold(*seed, |number, mapping| mapping.apply(number))
-        })
+        .map(|seed| composed.apply(*seed))
         .min()
         .unwrap()
 }
 
-fn solve2(almanac: &Almanac) -> i64 {
+fn solve2(almanac: &Almanac, composed: &PiecewiseMap) -> i64 {
     almanac
-        .maps
-        .iter()
-        .fold(almanac.seed_ranges(), |ranges, mapping| {
-            mapping.apply_ranges(&ranges)
-        })
+        .seed_ranges()
         .iter()
+        .flat_map(|range| composed.apply_range(range))
         .map(|r| r.start)
         .min()
         .unwrap()
 }
 
+fn solve2_inverse(almanac: &Almanac) -> i64 {
+    let seed_ranges = almanac.seed_ranges();
+    let reaches_seed = |location: i64| {
+        almanac
+            .seeds_with_location_at_most(location)
+            .iter()
+            .any(|r| seed_ranges.iter().any(|s| s.intersects(r)))
+    };
+    let (mut low, mut high) = (0, DOMAIN_END - 1);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if reaches_seed(mid) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    low
+}
+
 fn main() -> Result<(), Error> {
     let almanac = Almanac::from_lines(
         &fs::read_to_string(Path::new("data/input05.txt"))?
             .lines()
             .collect::<Vec<_>>(),
     )?;
-    println!("{}", solve(&almanac));
-    println!("{}", solve2(&almanac));
+    let (composed, num_segments) = almanac.compose("seed", "location")?;
+    println!("{}", solve(&almanac, &composed));
+    println!("{}", solve2(&almanac, &composed));
+    if std::env::args().any(|arg| arg == "print") {
+        println!("Segments per stage: {:?}", num_segments);
+        print!("{}", composed);
+    }
+    if std::env::args().any(|arg| arg == "check") {
+        println!("Inverse search: {}", solve2_inverse(&almanac));
+    }
+    if std::env::args().any(|arg| arg == "validate") {
+        for mapping in &almanac.maps {
+            for issue in mapping.range_issues() {
+                println!("{}-to-{}: {}", mapping.source, mapping.destination, issue);
+            }
+        }
+    }
+    for arg in std::env::args().skip(1) {
+        if let Some((source, destination)) = arg.split_once("-to-") {
+            print!("{}", almanac.compose(source, destination)?.0);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "seeds: 79 14 55 13
+
+seed-to-soil map:
+50 98 2
+52 50 48
+
+soil-to-fertilizer map:
+0 15 37
+37 52 2
+39 0 15
+
+fertilizer-to-water map:
+49 53 8
+0 11 42
+42 0 7
+57 7 4
+
+water-to-light map:
+88 18 7
+18 25 70
+
+light-to-temperature map:
+45 77 23
+81 45 19
+68 64 13
+
+temperature-to-humidity map:
+0 69 1
+1 0 69
+
+humidity-to-location map:
+60 56 37
+56 93 4";
+
+    fn parse(input: &str) -> Result<Almanac, Error> {
+        Almanac::from_lines(&input.lines().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_domain() {
+        assert!(parse(&EXAMPLE.replace("79 14", "-1 14")).is_err());
+        assert!(parse(&EXAMPLE.replace("79 14", &format!("79 {}", DOMAIN_END))).is_err());
+        assert!(parse(&EXAMPLE.replace("0 69 1\n", &format!("{} 69 1\n", DOMAIN_END))).is_err());
+        assert!(parse(EXAMPLE).is_ok());
+    }
+
+    // The first range containing the value wins, as in the puzzle statement
+    fn apply_mapping(mapping: &Mapping, value: i64) -> i64 {
+        mapping
+            .ranges
+            .iter()
+            .find(|r| (r.source_start..r.source_start + r.length).contains(&value))
+            .map_or(value, |r| r.dest_start + value - r.source_start)
+    }
+
+    #[test]
+    fn test_example() {
+        let almanac = parse(EXAMPLE).unwrap();
+        let (composed, num_segments) = almanac.compose("seed", "location").unwrap();
+        assert_eq!(solve(&almanac, &composed), 35);
+        assert_eq!(solve2(&almanac, &composed), 46);
+        assert_eq!(num_segments.len(), 7);
+    }
+
+    #[test]
+    fn test_compose_matches_fold() {
+        let almanac = parse(EXAMPLE).unwrap();
+        let (composed, _) = almanac.compose("seed", "location").unwrap();
+        for seed in 0..200 {
+            let folded = almanac.maps.iter().fold(seed, |v, m| apply_mapping(m, v));
+            assert_eq!(composed.apply(seed), folded);
+        }
+        let brute_force = almanac
+            .seed_ranges()
+            .iter()
+            .flat_map(|r| r.start..r.start + r.length)
+            .map(|seed| almanac.maps.iter().fold(seed, |v, m| apply_mapping(m, v)))
+            .min();
+        assert_eq!(Some(solve2(&almanac, &composed)), brute_force);
+    }
+
+    #[test]
+    fn test_partial_compose() {
+        let almanac = parse(EXAMPLE).unwrap();
+        let (composed, num_segments) = almanac.compose("soil", "humidity").unwrap();
+        assert_eq!(num_segments.len(), 5);
+        for soil in 0..200 {
+            let folded = almanac.maps[1..6]
+                .iter()
+                .fold(soil, |v, m| apply_mapping(m, v));
+            assert_eq!(composed.apply(soil), folded);
+        }
+        assert!(almanac.compose("soil", "seed").is_err());
+        assert!(almanac.compose("dirt", "location").is_err());
+    }
+
+    #[test]
+    fn test_inverse() {
+        let almanac = parse(EXAMPLE).unwrap();
+        let (composed, _) = almanac.compose("seed", "location").unwrap();
+        assert_eq!(solve2_inverse(&almanac), solve2(&almanac, &composed));
+        for range in almanac.seeds_with_location_at_most(50) {
+            for seed in range.start..range.start + range.length {
+                assert!(composed.apply(seed) <= 50);
+            }
+        }
+        let count = |ranges: Vec<Range>| ranges.iter().map(|r| r.length).sum::<i64>();
+        assert_eq!(count(almanac.seeds_with_location_at_most(50)), 51);
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let (seeds, maps) = EXAMPLE.split_once("\n\n").unwrap();
+        let mut blocks: Vec<_> = maps.split("\n\n").collect();
+        blocks.reverse();
+        let shuffled = parse(&format!("{}\n\n{}", seeds, blocks.join("\n\n"))).unwrap();
+        let (composed, _) = shuffled.compose("seed", "location").unwrap();
+        assert_eq!(shuffled.maps[0].source, "seed");
+        assert_eq!(solve(&shuffled, &composed), 35);
+
+        let duplicate = format!("{}\n\nsoil-to-water map:\n1 2 3", EXAMPLE);
+        assert!(parse(&duplicate).is_err());
+        let dangling = format!("{}\n\nfoo-to-bar map:\n1 2 3", EXAMPLE);
+        assert!(parse(&dangling).is_err());
+        assert!(parse(&EXAMPLE.replace("humidity-to-location", "humidity-to-place")).is_err());
+    }
+
+    #[test]
+    fn test_range_issues() {
+        let lines = ["a-to-b map:", "0 10 5", "0 12 5", "0 20 5"];
+        let mapping = Mapping::from_iter(&mut lines.iter()).unwrap();
+        let issues: Vec<_> = mapping
+            .range_issues()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            issues,
+            vec!["overlapping sources [12, 15)", "gap in sources [17, 20)"]
+        );
+    }
+}
@@ -1,20 +1,43 @@
 use anyhow::Error;
 use aoc2023::parse_numbers;
+use num::{clamp, BigInt, One, Zero};
 use std::fs;
 use std::iter::zip;
 use std::path::Path;
 
 struct Race {
-    time: i64,
-    distance: i64,
+    time: BigInt,
+    distance: BigInt,
 }
 
 impl Race {
-    fn num_ways_to_beat(&self) -> u64 {
-        (0..self.time + 1)
-            .map(|p| p * (self.time - p))
-            .filter(|d| *d > self.distance)
-            .count() as u64
+    fn beats(&self, hold: &BigInt) -> bool {
+        hold * (&self.time - hold) > self.distance
+    }
+
+    fn num_ways_to_beat(&self) -> BigInt {
+        // Winning hold times p satisfy p^2 - time * p + distance < 0
+        let discriminant: BigInt = &self.time * &self.time - 4 * &self.distance;
+        if discriminant <= BigInt::zero() {
+            return BigInt::zero();
+        }
+        let mut first = clamp(
+            (&self.time - discriminant.sqrt()) / 2,
+            BigInt::zero(),
+            self.time.clone(),
+        );
+        while first > BigInt::zero() && self.beats(&(&first - 1)) {
+            first -= 1;
+        }
+        while first <= &self.time / 2 && !self.beats(&first) {
+            first += 1;
+        }
+        let last = &self.time - &first;
+        if first <= last {
+            last - first + BigInt::one()
+        } else {
+            BigInt::zero()
+        }
     }
 }
 
@@ -35,24 +58,32 @@ fn parse_input(input: &str) -> Result<Vec<Race>, Error> {
             .ok_or(Error::msg("No distance prefix"))?,
     )?;
     Ok(zip(times, distances)
-        .map(|(time, distance)| Race { time, distance })
+        .map(|(time, distance)| Race {
+            time: BigInt::from(time),
+            distance: BigInt::from(distance),
+        })
         .collect())
 }
 
-fn parse_as_big_number(line: &str) -> i64 {
-    line.chars().fold(0_i64, |acc, c| {
-        c.to_digit(10).map_or(acc, |d| acc * 10 + d as i64)
-    })
+fn parse_as_big_number(line: &str) -> Result<BigInt, Error> {
+    Ok(line
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()?)
 }
 fn parse_input_as_single_race(input: &str) -> Result<Race, Error> {
-    let values: Vec<_> = input.lines().map(parse_as_big_number).collect();
+    let values: Vec<_> = input
+        .lines()
+        .map(parse_as_big_number)
+        .collect::<Result<_, _>>()?;
     Ok(Race {
-        time: *values.first().ok_or(Error::msg("no time line"))?,
-        distance: *values.get(1).ok_or(Error::msg("no distance line"))?,
+        time: values.first().ok_or(Error::msg("no time line"))?.clone(),
+        distance: values.get(1).ok_or(Error::msg("no distance line"))?.clone(),
     })
 }
 
-fn solve(races: &[Race]) -> u64 {
+fn solve(races: &[Race]) -> BigInt {
     races.iter().map(Race::num_ways_to_beat).product()
 }
 
@@ -64,3 +95,40 @@ fn main() -> Result<(), Error> {
     println!("{}", mega_race.num_ways_to_beat());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brute_force(time: i64, distance: i64) -> BigInt {
+        BigInt::from((0..time + 1).filter(|p| p * (time - p) > distance).count())
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        for time in 0..60 {
+            for distance in 0..time * time / 4 + 2 {
+                let race = Race {
+                    time: BigInt::from(time),
+                    distance: BigInt::from(distance),
+                };
+                assert_eq!(race.num_ways_to_beat(), brute_force(time, distance));
+            }
+        }
+    }
+
+    #[test]
+    fn test_beyond_i64() {
+        let time = BigInt::from(10).pow(30);
+        let race = Race {
+            time: time.clone(),
+            distance: &time * &time / 4 - 1,
+        };
+        assert_eq!(race.num_ways_to_beat(), BigInt::one());
+        let race = Race {
+            time: time.clone(),
+            distance: BigInt::zero(),
+        };
+        assert_eq!(race.num_ways_to_beat(), time - 1);
+    }
+}
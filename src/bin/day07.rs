@@ -1,135 +1,126 @@
 use anyhow::Error;
-use std::cmp::Ordering;
+use aoc2023::XorShift;
+use std::cmp::{max, min, Ordering, Reverse};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use std::time::Instant;
 
-#[derive(Ord, PartialOrd, Eq, PartialEq, Copy, Clone, Hash, Debug)]
-struct Card(i8);
-
-impl Card {
-    fn from_char(c: char) -> Self {
-        Self(c.to_digit(10).unwrap_or_else(|| match c {
-            'T' => 10,
-            'J' => 11,
-            'Q' => 12,
-            'K' => 13,
-            'A' => 14,
-            _ => panic!("invalid card"),
-        }) as i8)
-    }
+struct RuleSet {
+    hand_size: usize,
+    ranks: Vec<char>,
+    wilds: Vec<char>,
+    wild_value: i32,
+}
 
-    fn joker_value(&self) -> i8 {
-        if self.0 == 11 {
-            1
-        } else {
-            self.0
+impl RuleSet {
+    fn new(hand_size: usize, ranks: &str, wilds: &str, wild_value: i32) -> Self {
+        Self {
+            hand_size,
+            ranks: ranks.chars().collect(),
+            wilds: wilds.chars().collect(),
+            wild_value,
         }
     }
-}
-
-#[derive(Ord, PartialOrd, Eq, PartialEq)]
-enum HandType {
-    High = 0,
-    OnePair = 1,
-    TwoPair = 2,
-    Three = 3,
-    FullHouse = 4,
-    Four = 5,
-    Five = 6,
-}
-
-#[derive(Eq, PartialEq, Clone, Debug)]
-struct Hand {
-    cards: [Card; 5],
-}
 
-impl Hand {
-    fn get_histogram(&self) -> HashMap<Card, i8> {
-        let mut histogram: HashMap<Card, i8> = HashMap::new();
-        for card in self.cards {
-            histogram.entry(card).and_modify(|e| *e += 1).or_insert(1);
-        }
-        histogram
+    fn standard() -> Self {
+        Self::new(5, "23456789TJQKA", "", 0)
     }
 
-    fn get_sorted_card_counts(&self) -> Vec<i8> {
-        let mut counts: Vec<i8> = self.get_histogram().values().cloned().collect();
-        counts.sort_by_key(|c| -*c);
-        counts
+    fn jokers() -> Self {
+        Self::new(5, "23456789TJQKA", "J", -1)
     }
 
-    fn get_joker_sorted_card_counts(&self) -> Vec<i8> {
-        let mut histogram = self.get_histogram();
-        let num_jokers = histogram.remove(&Card::from_char('J')).unwrap_or(0);
-        if num_jokers == 5 {
-            vec![5]
-        } else {
-            let mut counts: Vec<i8> = histogram.values().cloned().collect();
-            counts.sort_by_key(|c| -*c);
-            counts[0] += num_jokers;
-            counts
+    fn from_args(args: &[String]) -> Result<Self, Error> {
+        if args.len() != 4 {
+            return Err(Error::msg("Expected: hand_size ranks wilds wild_value"));
         }
+        Ok(Self::new(
+            args[0].parse()?,
+            &args[1],
+            &args[2],
+            args[3].parse()?,
+        ))
     }
 
-    fn hand_type(&self) -> HandType {
-        self.hand_type_with_count_fn(Self::get_sorted_card_counts)
+    // Field widths for the packed sort key, or None if the hand doesn't fit in 128 bits
+    fn key_bits(&self) -> Option<(u32, u32)> {
+        let count_bits = u128::BITS - (self.hand_size as u128).leading_zeros();
+        let value_bits =
+            u128::BITS - ((self.max_value() - self.min_value()) as u128).leading_zeros();
+        let total_bits = self
+            .hand_size
+            .checked_mul((count_bits + value_bits) as usize)?;
+        (total_bits <= u128::BITS as usize).then_some((count_bits, value_bits))
     }
 
-    fn joker_hand_type(&self) -> HandType {
-        self.hand_type_with_count_fn(Self::get_joker_sorted_card_counts)
+    fn min_value(&self) -> i64 {
+        min(self.wild_value as i64, 0)
     }
 
-    fn hand_type_with_count_fn(&self, count_fn: fn(&Self) -> Vec<i8>) -> HandType {
-        let sorted_counts = count_fn(self);
+    fn max_value(&self) -> i64 {
+        max(self.wild_value as i64, self.ranks.len() as i64 - 1)
+    }
 
-        match sorted_counts.len() {
-            1 => HandType::Five,
-            2 => {
-                if sorted_counts[0] == 4 {
-                    HandType::Four
-                } else {
-                    HandType::FullHouse
-                }
-            }
-            3 => {
-                if sorted_counts[0] == 3 {
-                    HandType::Three
-                } else {
-                    HandType::TwoPair
-                }
-            }
-            4 => HandType::OnePair,
-            5 => HandType::High,
-            _ => panic!("Logic error"),
+    fn sort_key(&self, hand: &Hand, (count_bits, value_bits): (u32, u32)) -> u128 {
+        // Counts and card values are packed into fixed-width fields, most significant first
+        let min_value = self.min_value();
+        let counts = hand.get_sorted_card_counts(self);
+        let mut key = 0_u128;
+        for i in 0..self.hand_size {
+            key = (key << count_bits) | *counts.get(i).unwrap_or(&0) as u128;
         }
+        for value in hand.card_values(self) {
+            key = (key << value_bits) | (value - min_value) as u128;
+        }
+        key
     }
 
-    fn joker_cmp(&self, other: &Self) -> Ordering {
-        (
-            self.joker_hand_type(),
-            self.cards.iter().map(Card::joker_value).collect::<Vec<_>>(),
-        )
-            .cmp(&(
-                other.joker_hand_type(),
-                other
-                    .cards
-                    .iter()
-                    .map(Card::joker_value)
-                    .collect::<Vec<_>>(),
-            ))
+    fn card_value(&self, card: char) -> i64 {
+        if self.wilds.contains(&card) {
+            self.wild_value as i64
+        } else {
+            self.ranks.iter().position(|&c| c == card).unwrap() as i64
+        }
     }
 }
 
-impl PartialOrd<Self> for Hand {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+#[derive(Eq, PartialEq, Clone, Debug)]
+struct Hand {
+    cards: Vec<char>,
 }
 
-impl Ord for Hand {
-    fn cmp(&self, other: &Self) -> Ordering {
-        (self.hand_type(), self.cards).cmp(&(other.hand_type(), other.cards))
+impl Hand {
+    fn get_sorted_card_counts(&self, rules: &RuleSet) -> Vec<usize> {
+        let mut histogram: HashMap<char, usize> = HashMap::new();
+        let mut num_wilds = 0;
+        for &card in &self.cards {
+            if rules.wilds.contains(&card) {
+                num_wilds += 1;
+            } else {
+                histogram.entry(card).and_modify(|e| *e += 1).or_insert(1);
+            }
+        }
+        let mut counts: Vec<usize> = histogram.values().cloned().collect();
+        counts.sort_by_key(|&c| Reverse(c));
+        match counts.first_mut() {
+            Some(largest) => *largest += num_wilds,
+            None => counts.push(num_wilds),
+        }
+        counts
+    }
+
+    fn card_values(&self, rules: &RuleSet) -> Vec<i64> {
+        self.cards.iter().map(|&c| rules.card_value(c)).collect()
+    }
+
+    fn cmp_with_rules(&self, other: &Self, rules: &RuleSet) -> Ordering {
+        // Sorted counts compare lexicographically in the same order as the hand types,
+        // e.g. [3, 2] (full house) beats [3, 1, 1] (three of a kind)
+        (self.get_sorted_card_counts(rules), self.card_values(rules)).cmp(&(
+            other.get_sorted_card_counts(rules),
+            other.card_values(rules),
+        ))
     }
 }
 
@@ -139,27 +130,50 @@ struct HandWithBid {
     bid: u64,
 }
 
-fn parse_line(line: &str) -> Result<HandWithBid, Error> {
+fn parse_line(line: &str, rules: &RuleSet) -> Result<HandWithBid, Error> {
     let (hand_str, bid_str) = line
         .split_once(' ')
         .ok_or(Error::msg("Two parts expected"))?;
-    let card_vec: Vec<_> = hand_str.chars().map(Card::from_char).collect();
-    let cards = card_vec
-        .try_into()
-        .map_err(|_| Error::msg("Five cards expected"))?;
+    let cards: Vec<_> = hand_str.chars().collect();
+    if cards.len() != rules.hand_size {
+        return Err(Error::msg(format!("{} cards expected", rules.hand_size)));
+    }
+    if let Some(card) = cards
+        .iter()
+        .find(|c| !rules.ranks.contains(c) && !rules.wilds.contains(c))
+    {
+        return Err(Error::msg(format!("invalid card {}", card)));
+    }
     let hand = Hand { cards };
     let bid = bid_str.parse::<u64>()?;
 
     Ok(HandWithBid { hand, bid })
 }
 
-fn parse_input(input: &str) -> Result<Vec<HandWithBid>, Error> {
-    input.lines().map(parse_line).collect()
+fn parse_input(input: &str, rules: &RuleSet) -> Result<Vec<HandWithBid>, Error> {
+    input.lines().map(|l| parse_line(l, rules)).collect()
 }
 
-fn solve(input: &[HandWithBid], cmp_hands: fn(&Hand, &Hand) -> Ordering) -> u64 {
+fn solve(input: &[HandWithBid], rules: &RuleSet) -> u64 {
+    let Some(key_bits) = rules.key_bits() else {
+        return solve_by_comparison(input, rules);
+    };
+    let mut keyed_bids: Vec<_> = input
+        .iter()
+        .map(|hb| (rules.sort_key(&hb.hand, key_bits), hb.bid))
+        .collect();
+    keyed_bids.sort_by_key(|(key, _)| *key);
+
+    keyed_bids
+        .iter()
+        .enumerate()
+        .map(|(i, (_, bid))| (i + 1) as u64 * bid)
+        .sum()
+}
+
+fn solve_by_comparison(input: &[HandWithBid], rules: &RuleSet) -> u64 {
     let mut sorted_hands = input.to_vec();
-    sorted_hands.sort_by(|a, b| cmp_hands(&a.hand, &b.hand));
+    sorted_hands.sort_by(|a, b| a.hand.cmp_with_rules(&b.hand, rules));
 
     sorted_hands
         .iter()
@@ -167,10 +181,111 @@ fn solve(input: &[HandWithBid], cmp_hands: fn(&Hand, &Hand) -> Ordering) -> u64
         .map(|(i, hb)| (i + 1) as u64 * hb.bid)
         .sum()
 }
+
+fn generate_input(num_hands: usize, rules: &RuleSet) -> Vec<HandWithBid> {
+    let mut rng = XorShift::new(0x2545f4914f6cdd1d);
+    (0..num_hands)
+        .map(|_| HandWithBid {
+            hand: Hand {
+                cards: (0..rules.hand_size)
+                    .map(|_| rules.ranks[rng.next_u64() as usize % rules.ranks.len()])
+                    .collect(),
+            },
+            bid: rng.next_u64() % 1000 + 1,
+        })
+        .collect()
+}
+
+fn benchmark(num_hands: usize) {
+    for rules in [RuleSet::standard(), RuleSet::jokers()] {
+        let input = generate_input(num_hands, &rules);
+
+        let start = Instant::now();
+        let by_key = solve(&input, &rules);
+        let key_time = start.elapsed();
+
+        let start = Instant::now();
+        let by_comparison = solve_by_comparison(&input, &rules);
+        let comparison_time = start.elapsed();
+
+        assert_eq!(by_key, by_comparison);
+        println!(
+            "{} hands: sort key {:?}, comparison {:?}",
+            num_hands, key_time, comparison_time
+        );
+    }
+}
 fn main() -> Result<(), Error> {
-    let input = parse_input(&fs::read_to_string(Path::new("data/input07.txt"))?)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench") {
+        benchmark(args.get(1).map_or(Ok(1_000_000), |n| n.parse())?);
+        return Ok(());
+    }
+    if let Some((file_name, rule_args)) = args.split_first() {
+        let rules = RuleSet::from_args(rule_args)?;
+        let input = parse_input(&fs::read_to_string(Path::new(file_name))?, &rules)?;
+        println!("{}", solve(&input, &rules));
+        return Ok(());
+    }
 
-    println!("{}", solve(&input, Hand::cmp));
-    println!("{}", solve(&input, Hand::joker_cmp));
+    let raw_input = fs::read_to_string(Path::new("data/input07.txt"))?;
+    for rules in [RuleSet::standard(), RuleSet::jokers()] {
+        println!("{}", solve(&parse_input(&raw_input, &rules)?, &rules));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_key_matches_comparison() {
+        for rules in [
+            RuleSet::standard(),
+            RuleSet::jokers(),
+            RuleSet::new(12, "23456789TJQKA", "J", -1),
+            RuleSet::new(17, "23456789TJQKA", "", 0),
+        ] {
+            let input = generate_input(2000, &rules);
+            assert_eq!(solve(&input, &rules), solve_by_comparison(&input, &rules));
+        }
+    }
+
+    const EXAMPLE: &str = "32T3K 765\nT55J5 684\nKK677 28\nKTJJT 220\nQQQJA 483";
+
+    #[test]
+    fn test_example() {
+        for (rules, expected) in [(RuleSet::standard(), 6440), (RuleSet::jokers(), 5905)] {
+            let input = parse_input(EXAMPLE, &rules).unwrap();
+            assert_eq!(solve(&input, &rules), expected);
+            assert_eq!(solve_by_comparison(&input, &rules), expected);
+        }
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        // Three-card hands with two wild cards: 345 < K3K < JQJ < J2Q < 222
+        let rules = RuleSet::new(3, "23456789TJQKA", "JQ", -1);
+        let input = parse_input("222 1\nJ2Q 2\n345 3\nK3K 4\nJQJ 5", &rules).unwrap();
+        assert_eq!(solve(&input, &rules), 3 + 4 * 2 + 5 * 3 + 2 * 4 + 5);
+    }
+
+    #[test]
+    fn test_extreme_rules() {
+        let rules = RuleSet::new(5, "23456789TJQKA", "J", -128);
+        assert_eq!(solve(&parse_input(EXAMPLE, &rules).unwrap(), &rules), 5905);
+
+        let rules = RuleSet::new(130, "23", "", 0);
+        let input = generate_input(50, &rules);
+        assert_eq!(solve(&input, &rules), solve_by_comparison(&input, &rules));
+
+        let ranks: String = (0..200)
+            .map(|i| char::from_u32(0x100 + i).unwrap())
+            .collect();
+        let rules = RuleSet::new(1, &ranks, "", 0);
+        let last = ranks.chars().last().unwrap();
+        let input = parse_input(&format!("{} 1\n\u{100} 2", last), &rules).unwrap();
+        assert_eq!(solve(&input, &rules), 2 + 2);
+    }
+}
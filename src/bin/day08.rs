@@ -1,9 +1,13 @@
 use anyhow::Error;
-use num::integer::lcm;
-use std::collections::HashMap;
+use num::integer::{lcm, ExtendedGcd};
+use num::Integer;
+use std::cmp::max;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
+use std::iter::zip;
 use std::path::Path;
 
+#[derive(Copy, Clone)]
 enum Direction {
     Left = 0,
     Right = 1,
@@ -21,18 +25,50 @@ impl Direction {
 
 struct Map {
     instructions: Vec<Direction>,
-    network: HashMap<String, (String, String)>,
+    names: Vec<String>,
+    successors: Vec<[usize; 2]>,
 }
 
-fn parse_node(line: &str) -> (String, (String, String)) {
-    let parts: Vec<_> = line.split(|c: char| !c.is_ascii_alphabetic()).collect();
-    (
-        String::from(parts[0]),
-        (String::from(parts[4]), String::from(parts[6])),
-    )
+impl Map {
+    fn node_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    fn end_nodes(&self, is_end_node: fn(&str) -> bool) -> Vec<bool> {
+        self.names.iter().map(|n| is_end_node(n)).collect()
+    }
+
+    fn to_dot(&self, cycles: &[GhostCycle]) -> String {
+        const COLORS: [&str; 6] = ["red", "blue", "green", "orange", "purple", "brown"];
+        let mut ret = String::from("digraph network {\n");
+        for (node, name) in self.names.iter().enumerate() {
+            ret += &format!("  n{} [label=\"{}\"];\n", node, name);
+        }
+        for (node, successors) in self.successors.iter().enumerate() {
+            ret += &format!("  n{} -> n{} [label=\"L\"];\n", node, successors[0]);
+            ret += &format!("  n{} -> n{} [label=\"R\"];\n", node, successors[1]);
+        }
+        for (ghost, cycle) in cycles.iter().enumerate() {
+            let color = COLORS[ghost % COLORS.len()];
+            let edges: BTreeSet<_> =
+                zip(&cycle.cycle_nodes, cycle.cycle_nodes.iter().cycle().skip(1)).collect();
+            for (from, to) in edges {
+                ret += &format!(
+                    "  n{} -> n{} [color={}, penwidth=2, constraint=false];\n",
+                    from, to, color
+                );
+            }
+        }
+        ret + "}\n"
+    }
+}
+
+fn parse_node(line: &str) -> (&str, (&str, &str)) {
+    let parts: Vec<_> = line.split(|c: char| !c.is_ascii_alphanumeric()).collect();
+    (parts[0], (parts[4], parts[6]))
 }
 
-fn parse_input(input: &str) -> Map {
+fn parse_input(input: &str) -> Result<Map, Error> {
     let mut lines = input.lines();
     let instructions = lines
         .next()
@@ -41,48 +77,239 @@ fn parse_input(input: &str) -> Map {
         .map(Direction::from_char)
         .collect();
 
-    let network = lines.skip(1).map(parse_node).collect();
+    let nodes: Vec<_> = lines.skip(1).map(parse_node).collect();
+    let names: Vec<String> = nodes.iter().map(|(name, _)| String::from(*name)).collect();
+    let indices: HashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, i))
+        .collect();
+    let index = |name: &str| {
+        indices
+            .get(name)
+            .copied()
+            .ok_or(Error::msg(format!("Node {} is never defined", name)))
+    };
+    let successors = nodes
+        .iter()
+        .map(|(_, (left, right))| Ok([index(left)?, index(right)?]))
+        .collect::<Result<_, Error>>()?;
 
-    Map {
+    Ok(Map {
         instructions,
-        network,
-    }
+        names,
+        successors,
+    })
 }
 
 fn solve(map: &Map, start_node: &str, is_end_node: fn(&str) -> bool) -> u64 {
+    let end_nodes = map.end_nodes(is_end_node);
     let cycle_instructions = map.instructions.iter().cycle();
 
-    let mut current_node = String::from(start_node);
+    let mut current_node = map.node_index(start_node).unwrap();
     let mut num_steps = 0;
-    for instruction in cycle_instructions {
-        let current_paths = map.network.get(&*current_node).unwrap();
-        current_node = match instruction {
-            Direction::Left => current_paths.0.clone(),
-            Direction::Right => current_paths.1.clone(),
-        };
+    for &instruction in cycle_instructions {
+        current_node = map.successors[current_node][instruction as usize];
         num_steps += 1;
 
-        if is_end_node(&current_node) {
+        if end_nodes[current_node] {
             break;
         }
     }
     num_steps
 }
 
-fn solve2(map: &Map) -> u64 {
-    let is_end_node = |node: &str| node.ends_with('Z');
-    map.network
-        .keys()
-        .filter(|n| n.ends_with('A'))
-        .map(|start_node| solve(map, start_node, is_end_node))
-        .reduce(lcm)
-        .unwrap()
+struct GhostCycle {
+    cycle_start: u64,
+    period: u64,
+    hits_before_cycle: Vec<u64>,
+    hits_in_cycle: Vec<u64>,
+    cycle_nodes: Vec<usize>,
+}
+
+impl GhostCycle {
+    fn is_hit(&self, t: u64) -> bool {
+        if t < self.cycle_start {
+            self.hits_before_cycle.contains(&t)
+        } else {
+            let offset = (t - self.cycle_start) % self.period;
+            self.hits_in_cycle.contains(&(self.cycle_start + offset))
+        }
+    }
+
+    fn lcm_compatible(&self) -> bool {
+        self.hits_before_cycle.is_empty() && self.hits_in_cycle == [self.period]
+    }
+}
+
+fn ghost_cycle(map: &Map, start_node: usize, end_nodes: &[bool]) -> GhostCycle {
+    let num_instructions = map.instructions.len();
+    let mut seen: Vec<Option<u64>> = vec![None; map.names.len() * num_instructions];
+    let mut visited = vec![];
+    let mut hits = vec![];
+    let mut current_node = start_node;
+    let mut t = 0;
+    let cycle_start = loop {
+        let instruction_index = t as usize % num_instructions;
+        let state = current_node * num_instructions + instruction_index;
+        if let Some(first_seen) = seen[state] {
+            break first_seen;
+        }
+        seen[state] = Some(t);
+        visited.push(current_node);
+        if end_nodes[current_node] {
+            hits.push(t);
+        }
+        current_node = map.successors[current_node][map.instructions[instruction_index] as usize];
+        t += 1;
+    };
+    let (hits_before_cycle, hits_in_cycle) = hits.iter().partition(|&&h| h < cycle_start);
+
+    GhostCycle {
+        cycle_start,
+        period: t - cycle_start,
+        hits_before_cycle,
+        hits_in_cycle,
+        cycle_nodes: visited.split_off(cycle_start as usize),
+    }
+}
+
+fn combine_congruences(a: (i128, i128), b: (i128, i128)) -> Option<(i128, i128)> {
+    let ((r1, m1), (r2, m2)) = (a, b);
+    let ExtendedGcd { gcd, x, .. } = m1.extended_gcd(&m2);
+    if (r2 - r1) % gcd != 0 {
+        return None;
+    }
+    let modulus = m1 / gcd * m2;
+    let k = ((r2 - r1) / gcd * x).mod_floor(&(m2 / gcd));
+    Some(((r1 + m1 * k).mod_floor(&modulus), modulus))
+}
+
+fn ghost_cycles(map: &Map) -> Vec<GhostCycle> {
+    let end_nodes = map.end_nodes(|node| node.ends_with('Z'));
+    (0..map.names.len())
+        .filter(|&n| map.names[n].ends_with('A'))
+        .map(|start_node| ghost_cycle(map, start_node, &end_nodes))
+        .collect()
+}
+
+fn solve2(cycles: &[GhostCycle]) -> Option<u64> {
+    if cycles.iter().all(GhostCycle::lcm_compatible) {
+        return cycles.iter().map(|c| c.period).reduce(lcm);
+    }
+
+    // All ghosts are only guaranteed to be in their cycle from the last cycle start on
+    let cycle_start = cycles.iter().map(|c| c.cycle_start).max()?;
+    if let Some(t) = (1..cycle_start).find(|&t| cycles.iter().all(|c| c.is_hit(t))) {
+        return Some(t);
+    }
+
+    let mut congruences = vec![(0_i128, 1_i128)];
+    for cycle in cycles {
+        let period = cycle.period as i128;
+        congruences = congruences
+            .iter()
+            .flat_map(|&congruence| {
+                cycle.hits_in_cycle.iter().filter_map(move |&hit| {
+                    combine_congruences(congruence, ((hit as i128).mod_floor(&period), period))
+                })
+            })
+            .collect();
+    }
+    congruences
+        .iter()
+        .map(|&(residue, modulus)| {
+            let start = max(cycle_start, 1) as i128;
+            start + (residue - start).mod_floor(&modulus)
+        })
+        .min()
+        .map(|t| t as u64)
 }
 
 fn main() -> Result<(), Error> {
-    let map = parse_input(&fs::read_to_string(Path::new("data/input08.txt"))?);
+    let map = parse_input(&fs::read_to_string(Path::new("data/input08.txt"))?)?;
     println!("{}", solve(&map, "AAA", |s| s == "ZZZ"));
-    println!("{}", solve2(&map));
+    let cycles = ghost_cycles(&map);
+    match solve2(&cycles) {
+        Some(steps) => println!("{}", steps),
+        None => println!("The ghosts never all reach an end node together"),
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [command, file_name] = &args[..] {
+        if command == "dot" {
+            fs::write(Path::new(file_name), map.to_dot(&cycles))?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "LR\n\n11A = (11B, XXX)\n11B = (XXX, 11Z)\n11Z = (11B, XXX)\n\
+                           22A = (22B, XXX)\n22B = (22C, 22C)\n22C = (22Z, 22Z)\n\
+                           22Z = (22B, 22B)\nXXX = (XXX, XXX)";
+
+    #[test]
+    fn test_example() {
+        let map = parse_input(EXAMPLE).unwrap();
+        assert_eq!(solve2(&ghost_cycles(&map)), Some(6));
+    }
+
+    #[test]
+    fn test_offset_cycles() {
+        // Ends are reached at 2, 5, 8, ... and 1, 3, 5, ..., so the LCM of 6 is wrong
+        let map = parse_input(
+            "L\n\n1A = (1B, 1B)\n1B = (1Z, 1Z)\n1Z = (1A, 1A)\n2A = (2Z, 2Z)\n2Z = (2A, 2A)",
+        )
+        .unwrap();
+        assert_eq!(solve2(&ghost_cycles(&map)), Some(5));
+    }
+
+    #[test]
+    fn test_never_aligned() {
+        let map = parse_input(
+            "L\n\n1A = (1Z, 1Z)\n1Z = (1B, 1B)\n1B = (1Z, 1Z)\n2A = (2B, 2B)\n2B = (2Z, 2Z)\n2Z = (2B, 2B)",
+        )
+        .unwrap();
+        assert_eq!(solve2(&ghost_cycles(&map)), None);
+    }
+
+    #[test]
+    fn test_undefined_node() {
+        let error = parse_input("L\n\nAAA = (BBB, ZZZ)\nZZZ = (ZZZ, ZZZ)")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Node BBB is never defined");
+    }
+
+    #[test]
+    fn test_dot() {
+        let map = parse_input(EXAMPLE).unwrap();
+        let dot = map.to_dot(&ghost_cycles(&map));
+        assert!(dot.starts_with("digraph network {\n") && dot.ends_with("}\n"));
+        let lines: Vec<_> = dot.lines().map(str::trim).collect();
+        assert!(lines.contains(&"n0 [label=\"11A\"];"));
+        assert!(lines.contains(&"n7 [label=\"XXX\"];"));
+        assert!(lines.contains(&"n1 -> n2 [label=\"R\"];"));
+        assert!(lines.contains(&"n7 -> n7 [label=\"L\"];"));
+        assert_eq!(
+            lines.iter().filter(|l| l.contains("[label=")).count(),
+            8 + 16
+        );
+
+        let cycle_edges: Vec<_> = lines.iter().filter(|l| l.contains("color=")).collect();
+        assert_eq!(
+            cycle_edges,
+            vec![
+                &"n1 -> n2 [color=red, penwidth=2, constraint=false];",
+                &"n2 -> n1 [color=red, penwidth=2, constraint=false];",
+                &"n4 -> n5 [color=blue, penwidth=2, constraint=false];",
+                &"n5 -> n6 [color=blue, penwidth=2, constraint=false];",
+                &"n6 -> n4 [color=blue, penwidth=2, constraint=false];",
+            ]
+        );
+    }
+}
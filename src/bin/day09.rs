@@ -1,5 +1,7 @@
 use anyhow::Error;
 use aoc2023::parse_numbers;
+use num::{BigInt, BigRational, One, ToPrimitive, Zero};
+use std::collections::BTreeMap;
 use std::fs;
 use std::iter::zip;
 use std::path::Path;
@@ -10,40 +12,146 @@ fn compute_diffs(numbers: &[i64]) -> Vec<i64> {
         .collect()
 }
 
-type SequenceSolveFn = fn(&[Vec<i64>]) -> i64;
+struct Polynomial {
+    // Lowest degree first, as a function of the index in the sequence
+    coefficients: Vec<BigRational>,
+    length: usize,
+}
+
+impl Polynomial {
+    fn fit(numbers: &[i64]) -> Result<Self, Error> {
+        let mut sequences = vec![numbers.to_owned()];
+        while !sequences.last().unwrap().iter().all(|num| *num == 0) {
+            sequences.push(compute_diffs(sequences.last().unwrap()));
+        }
+        if sequences.last().unwrap().is_empty() {
+            return Err(Error::msg(format!(
+                "Difference table of {:?} never reaches all zeros",
+                numbers
+            )));
+        }
+
+        // Newton's forward formula: f(x) = sum_k diff_k(0) * x (x - 1) ... (x - k + 1) / k!
+        let mut coefficients = vec![BigRational::zero(); sequences.len()];
+        let mut falling_factorial = vec![BigRational::one()];
+        let mut factorial = BigRational::one();
+        for (k, sequence) in sequences.iter().enumerate() {
+            let scale = BigRational::from_integer(BigInt::from(sequence[0])) / &factorial;
+            for (power, coefficient) in falling_factorial.iter().enumerate() {
+                coefficients[power] += coefficient * &scale;
+            }
+            // Multiply by (x - k)
+            let shifted_k = BigRational::from_integer(BigInt::from(k));
+            let mut next = vec![BigRational::zero(); falling_factorial.len() + 1];
+            for (power, coefficient) in falling_factorial.iter().enumerate() {
+                next[power + 1] += coefficient;
+                next[power] -= coefficient * &shifted_k;
+            }
+            falling_factorial = next;
+            factorial *= BigRational::from_integer(BigInt::from(k + 1));
+        }
+        while coefficients.len() > 1 && coefficients.last().unwrap().is_zero() {
+            coefficients.pop();
+        }
 
-fn solve_line(numbers: &Vec<i64>, sequence_solve_fn: SequenceSolveFn) -> i64 {
-    let mut sequences = vec![numbers.to_owned()];
-    while !sequences.last().unwrap().iter().all(|num| *num == 0) {
-        sequences.push(compute_diffs(sequences.last().unwrap()));
+        Ok(Self {
+            coefficients,
+            length: numbers.len(),
+        })
     }
-    sequence_solve_fn(&sequences)
-}
 
-fn next_end_value(sequences: &[Vec<i64>]) -> i64 {
-    sequences.iter().map(|s| *s.last().unwrap()).sum()
-}
+    fn degree(&self) -> usize {
+        self.coefficients.len() - 1
+    }
 
-fn next_start_value(sequences: &[Vec<i64>]) -> i64 {
-    sequences
-        .iter()
-        .map(|s| *s.first().unwrap())
-        .rev()
-        .reduce(|acc, v| v - acc)
-        .unwrap()
+    fn evaluate(&self, x: i64) -> BigRational {
+        let x = BigRational::from_integer(BigInt::from(x));
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(BigRational::zero(), |acc, c| acc * &x + c)
+    }
+
+    // Positive steps extrapolate past the end, negative ones before the start
+    fn extrapolate(&self, steps: i64) -> Result<i64, Error> {
+        let x = match steps {
+            0 => return Err(Error::msg("Steps must be non-zero")),
+            1.. => self.length as i64 - 1 + steps,
+            _ => steps,
+        };
+        self.evaluate(x)
+            .to_integer()
+            .to_i64()
+            .ok_or(Error::msg("Extrapolated value does not fit in an i64"))
+    }
 }
 
-fn solve(input: &[Vec<i64>], sequence_solve_fn: SequenceSolveFn) -> i64 {
-    input.iter().map(|l| solve_line(l, sequence_solve_fn)).sum()
+fn solve(input: &[Polynomial], steps: i64) -> Result<i64, Error> {
+    input.iter().map(|p| p.extrapolate(steps)).sum()
 }
 
 fn main() -> Result<(), Error> {
     let input: Vec<_> = fs::read_to_string(Path::new("data/input09.txt"))?
         .lines()
-        .map(parse_numbers)
+        .map(|l| Polynomial::fit(&parse_numbers(l)?))
         .collect::<Result<_, _>>()?;
-    println!("{}", solve(&input, next_end_value));
-    println!("{}", solve(&input, next_start_value));
+    println!("{}", solve(&input, 1)?);
+    println!("{}", solve(&input, -1)?);
+
+    for arg in std::env::args().skip(1) {
+        if arg == "degrees" {
+            let mut histogram: BTreeMap<usize, usize> = BTreeMap::new();
+            for polynomial in &input {
+                *histogram.entry(polynomial.degree()).or_default() += 1;
+            }
+            println!("{:?}", histogram);
+        } else {
+            let steps: i64 = arg.parse()?;
+            println!("{}: {}", steps, solve(&input, steps)?);
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [&str; 3] = ["0 3 6 9 12 15", "1 3 6 10 15 21", "10 13 16 21 30 45"];
+
+    fn fit_example() -> Vec<Polynomial> {
+        EXAMPLE
+            .iter()
+            .map(|l| Polynomial::fit(&parse_numbers(l).unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_example() {
+        let input = fit_example();
+        assert_eq!(solve(&input, 1).unwrap(), 114);
+        assert_eq!(solve(&input, -1).unwrap(), 2);
+        let degrees: Vec<_> = input.iter().map(Polynomial::degree).collect();
+        assert_eq!(degrees, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        let input = fit_example();
+        assert_eq!(input[0].extrapolate(3).unwrap(), 24);
+        assert_eq!(input[0].extrapolate(-2).unwrap(), -6);
+        // Triangular numbers (n + 1)(n + 2) / 2
+        assert_eq!(input[1].extrapolate(4).unwrap(), 55);
+        assert_eq!(input[1].extrapolate(-3).unwrap(), 1);
+        assert!(input[1].extrapolate(0).is_err());
+        let constant = Polynomial::fit(&[7, 7, 7]).unwrap();
+        assert_eq!(constant.degree(), 0);
+        assert_eq!(constant.extrapolate(-100).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_no_zero_differences() {
+        assert!(Polynomial::fit(&[1, 2, 4, 8]).is_err());
+    }
+}
@@ -25,39 +25,80 @@ fn get_directions(c: char) -> Option<[(i64, i64); 2]> {
     })
 }
 
-fn parse_input(input: &str) -> Input {
+fn tile_at(map: &[Vec<char>], pos: (i64, i64)) -> Option<char> {
+    let row = map.get(usize::try_from(pos.0).ok()?)?;
+    row.get(usize::try_from(pos.1).ok()?).copied()
+}
+
+fn trace_loop(map: &[Vec<char>], start: (i64, i64)) -> Option<Vec<(i64, i64)>> {
+    let mut path = vec![start];
+    let mut pos = start;
+    let mut dir = get_directions(tile_at(map, start)?)?[0];
+    loop {
+        pos = (pos.0 + dir.0, pos.1 + dir.1);
+        if pos == start {
+            return get_directions(tile_at(map, start)?)?
+                .contains(&inverse(&dir))
+                .then_some(path);
+        }
+        let dirs = get_directions(tile_at(map, pos)?)?;
+        dir = if dirs[0] == inverse(&dir) {
+            dirs[1]
+        } else if dirs[1] == inverse(&dir) {
+            dirs[0]
+        } else {
+            None?
+        };
+        path.push(pos);
+    }
+}
+
+fn parse_input(input: &str) -> Result<Input, Error> {
     let mut map: Vec<Vec<char>> = input.lines().map(|l| l.chars().collect()).collect();
     // Find start
     let start: (i64, i64) = {
         let mut start: Option<_> = None;
-        'row: for row in 0..map.len() as i64 {
-            for col in 0..map[0].len() as i64 {
-                if map[row as usize][col as usize] == 'S' {
-                    start = Some((row, col));
+        'row: for (row, line) in map.iter().enumerate() {
+            for (col, &tile) in line.iter().enumerate() {
+                if tile == 'S' {
+                    start = Some((row as i64, col as i64));
                     break 'row;
                 }
             }
         }
-        start.unwrap()
+        start.ok_or(Error::msg("No start tile"))?
     };
     // Replace start
     let mut needed_directions = vec![];
     for (dr, dc) in DIRECTIONS {
-        if let Some(dirs) = get_directions(map[(start.0 + dr) as usize][(start.1 + dc) as usize]) {
+        if let Some(dirs) = tile_at(&map, (start.0 + dr, start.1 + dc)).and_then(get_directions) {
             if dirs.contains(&(-dr, -dc)) {
                 needed_directions.push((dr, dc))
             }
         }
     }
-    assert_eq!(needed_directions.len(), 2);
+    if needed_directions.len() < 2 {
+        return Err(Error::msg(format!(
+            "Start {:?} connects to {} pipes, 2 needed",
+            start,
+            needed_directions.len()
+        )));
+    }
+    // With more than two connecting pipes, only the right pair closes the loop
     for tile in "-|7LFJ".chars() {
         let dirs = get_directions(tile).unwrap();
-        if dirs.contains(&needed_directions[0]) && dirs.contains(&needed_directions[1]) {
+        if needed_directions.contains(&dirs[0]) && needed_directions.contains(&dirs[1]) {
             map[start.0 as usize][start.1 as usize] = tile;
+            if trace_loop(&map, start).is_some() {
+                return Ok(Input { map, start });
+            }
         }
     }
 
-    Input { map, start }
+    Err(Error::msg(format!(
+        "No closed loop through start {:?}",
+        start
+    )))
 }
 
 fn next_pos(
@@ -76,7 +117,7 @@ fn next_pos(
     ((pos.0 + next_dir.0, pos.1 + next_dir.1), next_dir)
 }
 
-fn solve(input: &Input) -> i64 {
+fn farthest_point(input: &Input) -> ((i64, i64), i64) {
     let start_dirs =
         get_directions(input.map[input.start.0 as usize][input.start.1 as usize]).unwrap();
     let mut last_forward_dir = start_dirs[0];
@@ -97,7 +138,11 @@ fn solve(input: &Input) -> i64 {
         counter += 1;
     }
 
-    counter
+    (forward_pos, counter)
+}
+
+fn solve(input: &Input) -> i64 {
+    farthest_point(input).1
 }
 
 fn get_clean_map(input: &Input) -> Vec<Vec<char>> {
@@ -117,16 +162,15 @@ fn get_clean_map(input: &Input) -> Vec<Vec<char>> {
     result
 }
 
-fn solve2(input: &Input) -> i64 {
-    let clean_map = get_clean_map(input);
-    let mut ret = 0;
-    // Count line by line
-    for line in clean_map {
+fn inside_tiles(clean_map: &[Vec<char>]) -> Vec<Vec<bool>> {
+    let mut ret = vec![vec![false; clean_map[0].len()]; clean_map.len()];
+    // Scan line by line
+    for (row, line) in clean_map.iter().enumerate() {
         let mut inside: bool = false;
         let mut pipe_start: Option<char> = None;
-        for tile in line {
+        for (col, &tile) in line.iter().enumerate() {
             if tile == '.' && pipe_start.is_none() {
-                ret += inside as i64;
+                ret[row][col] = inside;
             } else if tile == '|' {
                 inside = !inside;
             } else if pipe_start.is_none() {
@@ -146,10 +190,195 @@ fn solve2(input: &Input) -> i64 {
     ret
 }
 
+fn solve2(input: &Input) -> i64 {
+    inside_tiles(&get_clean_map(input))
+        .iter()
+        .flatten()
+        .filter(|&&inside| inside)
+        .count() as i64
+}
+
+enum Shade {
+    Pipe,
+    Farthest,
+    Inside,
+    Outside,
+}
+
+impl Shade {
+    fn ansi(&self) -> &str {
+        match self {
+            Shade::Pipe => "\x1b[1;37m",
+            Shade::Farthest => "\x1b[1;31m",
+            Shade::Inside => "\x1b[42m",
+            Shade::Outside => "\x1b[2m",
+        }
+    }
+
+    fn rgb(&self) -> [u8; 3] {
+        match self {
+            Shade::Pipe => [255, 255, 255],
+            Shade::Farthest => [255, 0, 0],
+            Shade::Inside => [0, 160, 0],
+            Shade::Outside => [40, 40, 40],
+        }
+    }
+}
+
+fn box_glyph(tile: char) -> char {
+    match tile {
+        '|' => '│',
+        '-' => '─',
+        'L' => '└',
+        'J' => '┘',
+        '7' => '┐',
+        'F' => '┌',
+        _ => '·',
+    }
+}
+
+struct Rendering {
+    tiles: Vec<Vec<(char, Shade)>>,
+}
+
+impl Rendering {
+    fn new(input: &Input) -> Self {
+        let clean_map = get_clean_map(input);
+        let inside = inside_tiles(&clean_map);
+        let farthest = farthest_point(input).0;
+        let tiles = clean_map
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                line.iter()
+                    .enumerate()
+                    .map(|(col, &tile)| {
+                        let shade = if (row as i64, col as i64) == farthest {
+                            Shade::Farthest
+                        } else if tile != '.' {
+                            Shade::Pipe
+                        } else if inside[row][col] {
+                            Shade::Inside
+                        } else {
+                            Shade::Outside
+                        };
+                        (tile, shade)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { tiles }
+    }
+
+    fn to_ansi(&self) -> String {
+        let mut ret = String::new();
+        for line in &self.tiles {
+            for (tile, shade) in line {
+                ret += &format!("{}{}\x1b[0m", shade.ansi(), box_glyph(*tile));
+            }
+            ret.push('\n');
+        }
+        ret
+    }
+
+    // Each tile becomes a 3x3 block of pixels, with the pipe drawn through the centre
+    fn to_ppm(&self) -> Vec<u8> {
+        let height = self.tiles.len() * 3;
+        let width = self.tiles[0].len() * 3;
+        let mut pixels = vec![[0_u8; 3]; width * height];
+        for (row, line) in self.tiles.iter().enumerate() {
+            for (col, (tile, shade)) in line.iter().enumerate() {
+                let center = (row as i64 * 3 + 1, col as i64 * 3 + 1);
+                let mut pipe_pixels = vec![];
+                if let Some(dirs) = get_directions(*tile) {
+                    pipe_pixels.push(center);
+                    for dir in dirs {
+                        pipe_pixels.push((center.0 + dir.0, center.1 + dir.1));
+                    }
+                } else {
+                    for dr in -1..2 {
+                        for dc in -1..2 {
+                            pipe_pixels.push((center.0 + dr, center.1 + dc));
+                        }
+                    }
+                }
+                for (r, c) in pipe_pixels {
+                    pixels[r as usize * width + c as usize] = shade.rgb();
+                }
+            }
+        }
+        let mut ret = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        ret.extend(pixels.iter().flatten());
+        ret
+    }
+}
+
 fn main() -> Result<(), Error> {
-    let input = parse_input(&fs::read_to_string(Path::new("data/input10.txt"))?);
+    let input = parse_input(&fs::read_to_string(Path::new("data/input10.txt"))?)?;
     println!("{}", solve(&input));
     println!("{}", solve2(&input));
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("render") => print!("{}", Rendering::new(&input).to_ansi()),
+        Some("ppm") => fs::write(
+            Path::new(args.get(1).ok_or(Error::msg("Output file expected"))?),
+            Rendering::new(&input).to_ppm(),
+        )?,
+        _ => {}
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_on_edge() {
+        let input = parse_input("S-7\n|.|\nL-J").unwrap();
+        assert_eq!(input.map[0][0], 'F');
+        assert_eq!(solve(&input), 4);
+    }
+
+    #[test]
+    fn test_ambiguous_start() {
+        let input = parse_input("F---7\n|...|\nS-7.|\n|.L-J\nL-...").unwrap();
+        assert_eq!(input.map[2][0], 'L');
+        assert_eq!(solve(&input), 7);
+    }
+
+    #[test]
+    fn test_no_loop() {
+        assert!(parse_input("S-7\n|..\nL-J").is_err());
+        assert!(parse_input("S..\n...").is_err());
+    }
+
+    #[test]
+    fn test_rendering() {
+        let input = parse_input(".....\n.S-7.\n.|.|.\n.L-J.\n.....").unwrap();
+        let rendering = Rendering::new(&input);
+        assert!(matches!(rendering.tiles[0][0].1, Shade::Outside));
+        assert!(matches!(rendering.tiles[1][2].1, Shade::Pipe));
+        assert!(matches!(rendering.tiles[2][2].1, Shade::Inside));
+        assert!(matches!(rendering.tiles[3][3].1, Shade::Farthest));
+
+        let ansi = rendering.to_ansi();
+        let plain: String = ansi
+            .split('\x1b')
+            .map(|part| part.split_once('m').map_or(part, |(_, rest)| rest))
+            .collect();
+        assert_eq!(plain, "·····\n·┌─┐·\n·│·│·\n·└─┘·\n·····\n");
+
+        let ppm = rendering.to_ppm();
+        let header = b"P6\n15 15\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + 15 * 15 * 3);
+        let pixel = |r: usize, c: usize| &ppm[header.len() + (r * 15 + c) * 3..][..3];
+        assert_eq!(pixel(7, 7), Shade::Inside.rgb());
+        assert_eq!(pixel(10, 10), Shade::Farthest.rgb());
+        assert_eq!(pixel(0, 0), Shade::Outside.rgb());
+        assert_eq!(pixel(4, 7), Shade::Pipe.rgb());
+    }
+}
@@ -1,6 +1,5 @@
 use anyhow::Error;
-use std::cmp::{max, min};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::ops::Sub;
 use std::path::Path;
@@ -10,6 +9,8 @@ struct Location {
     row: i64,
     col: i64,
 }
+type Coordinate = (i128, i128);
+
 struct Image {
     galaxies: Vec<Location>,
     empty_rows: Vec<i64>,
@@ -52,42 +53,262 @@ impl Image {
         }
     }
 
-    fn distance(&self, a: &Location, b: &Location, empty_dist: i64) -> i64 {
-        let min_row = min(a.row, b.row);
-        let max_row = max(a.row, b.row);
-        let min_col = min(a.col, b.col);
-        let max_col = max(a.col, b.col);
-        max_row - min_row + max_col - min_col
-            + (empty_dist - 1)
-                * (self
-                    .empty_rows
-                    .iter()
-                    .filter(|r| min_row < **r && **r < max_row)
-                    .count() as i64
-                    + self
-                        .empty_cols
-                        .iter()
-                        .filter(|r| min_col < **r && **r < max_col)
-                        .count() as i64)
+    fn expanded_coordinates(&self, row_factor: i128, col_factor: i128) -> Vec<Coordinate> {
+        let expand = |coord: i64, empty: &[i64], factor: i128| {
+            coord as i128 + (factor - 1) * empty.partition_point(|&e| e < coord) as i128
+        };
+        self.galaxies
+            .iter()
+            .map(|g| {
+                (
+                    expand(g.row, &self.empty_rows, row_factor),
+                    expand(g.col, &self.empty_cols, col_factor),
+                )
+            })
+            .collect()
     }
 }
 
-fn solve(image: &Image, empty_dist: i64) -> i64 {
+fn sum_of_pairwise_differences(mut values: Vec<i128>) -> i128 {
+    values.sort();
+    let mut prefix_sum = 0;
     let mut ret = 0;
-    for i in 0..image.galaxies.len() {
-        for j in i + 1..image.galaxies.len() {
-            ret += image.distance(&image.galaxies[i], &image.galaxies[j], empty_dist);
+    for (i, value) in values.iter().enumerate() {
+        ret += value * i as i128 - prefix_sum;
+        prefix_sum += value;
+    }
+    ret
+}
+
+fn solve(image: &Image, row_factor: i128, col_factor: i128) -> i128 {
+    let (rows, cols) = image
+        .expanded_coordinates(row_factor, col_factor)
+        .into_iter()
+        .unzip();
+    sum_of_pairwise_differences(rows) + sum_of_pairwise_differences(cols)
+}
+
+struct Universe {
+    coordinates: Vec<Coordinate>,
+}
+
+impl Universe {
+    fn new(image: &Image, row_factor: i128, col_factor: i128) -> Self {
+        Self {
+            coordinates: image.expanded_coordinates(row_factor, col_factor),
         }
     }
 
-    ret
+    fn distance(&self, a: usize, b: usize) -> i128 {
+        let (a, b) = (self.coordinates[a], self.coordinates[b]);
+        (a.0 - b.0).abs() + (a.1 - b.1).abs()
+    }
+
+    fn nearest(&self, galaxy: usize, k: usize) -> Result<Vec<(usize, i128)>, Error> {
+        if galaxy >= self.coordinates.len() {
+            return Err(Error::msg(format!(
+                "Galaxy {} out of range, there are {}",
+                galaxy,
+                self.coordinates.len()
+            )));
+        }
+        let mut others: Vec<_> = (0..self.coordinates.len())
+            .filter(|&g| g != galaxy)
+            .map(|g| (g, self.distance(galaxy, g)))
+            .collect();
+        others.sort_by_key(|&(g, d)| (d, g));
+        others.truncate(k);
+        Ok(others)
+    }
+
+    fn closest_pair(&self) -> Option<(usize, usize, i128)> {
+        let mut by_row: Vec<usize> = (0..self.coordinates.len()).collect();
+        by_row.sort_by_key(|&g| self.coordinates[g]);
+        let mut best: Option<(usize, usize, i128)> = None;
+        for (i, &a) in by_row.iter().enumerate() {
+            for &b in &by_row[i + 1..] {
+                let row_gap = self.coordinates[b].0 - self.coordinates[a].0;
+                if best.is_some_and(|(_, _, d)| row_gap >= d) {
+                    break;
+                }
+                let d = self.distance(a, b);
+                match best {
+                    Some((_, _, best_d)) if best_d <= d => {}
+                    _ => best = Some((a, b, d)),
+                }
+            }
+        }
+        best
+    }
+
+    fn farthest_pair(&self) -> Option<(usize, usize, i128)> {
+        // The Manhattan distance is the largest spread along one of the two diagonals
+        let diagonals: [fn(&Coordinate) -> i128; 2] = [|c| c.0 + c.1, |c| c.0 - c.1];
+        diagonals
+            .iter()
+            .filter_map(|diagonal| {
+                let key = |&g: &usize| diagonal(&self.coordinates[g]);
+                let a = (0..self.coordinates.len()).min_by_key(key)?;
+                let b = (0..self.coordinates.len()).max_by_key(key)?;
+                Some((a, b, self.distance(a, b)))
+            })
+            .max_by_key(|&(_, _, d)| d)
+    }
+
+    fn distance_histogram(&self, bucket_size: i128) -> Result<BTreeMap<i128, usize>, Error> {
+        if bucket_size <= 0 {
+            return Err(Error::msg("Bucket size must be positive"));
+        }
+        let mut ret = BTreeMap::new();
+        for a in 0..self.coordinates.len() {
+            for b in a + 1..self.coordinates.len() {
+                let bucket = self.distance(a, b) / bucket_size * bucket_size;
+                *ret.entry(bucket).or_default() += 1;
+            }
+        }
+        Ok(ret)
+    }
 }
 
 fn main() -> Result<(), Error> {
     let image = Image::from_str(&fs::read_to_string(Path::new("data/input11.txt"))?);
     for empty_dist in [2, 1000000] {
-        println!("{}", solve(&image, empty_dist));
+        println!("{}", solve(&image, empty_dist, empty_dist));
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        return Ok(());
+    }
+    let (row_factor, col_factor) = (args[0].parse()?, args[1].parse()?);
+    println!("{}", solve(&image, row_factor, col_factor));
+    let universe = Universe::new(&image, row_factor, col_factor);
+    let query: Vec<&str> = args[2..].iter().map(String::as_str).collect();
+    match query[..] {
+        ["nearest", galaxy, k] => {
+            for (g, d) in universe.nearest(galaxy.parse()?, k.parse()?)? {
+                println!("{}: {}", g, d);
+            }
+        }
+        ["pairs"] => {
+            println!("closest: {:?}", universe.closest_pair());
+            println!("farthest: {:?}", universe.farthest_pair());
+        }
+        ["histogram", bucket_size] => {
+            for (bucket, count) in universe.distance_histogram(bucket_size.parse()?)? {
+                println!("{}: {}", bucket, count);
+            }
+        }
+        [] => {}
+        _ => return Err(Error::msg("Unknown query")),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aoc2023::XorShift;
+
+    const EXAMPLE: &str = "...#......\n.......#..\n#.........\n..........\n......#...\n\
+                           .#........\n.........#\n..........\n.......#..\n#...#.....";
+
+    // Expands every galaxy and measures every pair directly
+    fn brute_force_distances(image: &Image, row_factor: i128, col_factor: i128) -> Vec<i128> {
+        let expand = |coord: i64, empty: &[i64], factor: i128| {
+            coord as i128 + (factor - 1) * empty.iter().filter(|&&e| e < coord).count() as i128
+        };
+        let coordinates: Vec<_> = image
+            .galaxies
+            .iter()
+            .map(|g| {
+                (
+                    expand(g.row, &image.empty_rows, row_factor),
+                    expand(g.col, &image.empty_cols, col_factor),
+                )
+            })
+            .collect();
+        let mut ret = vec![];
+        for (i, a) in coordinates.iter().enumerate() {
+            for b in &coordinates[i + 1..] {
+                ret.push((a.0 - b.0).abs() + (a.1 - b.1).abs());
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn test_example() {
+        let image = Image::from_str(EXAMPLE);
+        assert_eq!(solve(&image, 2, 2), 374);
+        assert_eq!(solve(&image, 10, 10), 1030);
+        assert_eq!(solve(&image, 100, 100), 8410);
+    }
+
+    #[test]
+    fn test_unequal_factors() {
+        let image = Image::from_str(EXAMPLE);
+        for (row_factor, col_factor) in [(1, 7), (3, 1), (1000000, 5), (0, 2)] {
+            let distances = brute_force_distances(&image, row_factor, col_factor);
+            assert_eq!(
+                solve(&image, row_factor, col_factor),
+                distances.iter().sum::<i128>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_pairs() {
+        let mut rng = XorShift::new(11);
+        let random_image: String = (0..30)
+            .map(|_| {
+                (0..40)
+                    .map(|_| {
+                        if rng.next_u64() < u64::MAX / 12 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        for (input, row_factor, col_factor) in
+            [(EXAMPLE, 2, 2), (EXAMPLE, 1, 50), (&random_image, 7, 3)]
+        {
+            let image = Image::from_str(input);
+            let universe = Universe::new(&image, row_factor, col_factor);
+            let distances = brute_force_distances(&image, row_factor, col_factor);
+
+            let (a, b, d) = universe.closest_pair().unwrap();
+            assert_eq!(Some(&d), distances.iter().min());
+            assert_eq!(universe.distance(a, b), d);
+            let (a, b, d) = universe.farthest_pair().unwrap();
+            assert_eq!(Some(&d), distances.iter().max());
+            assert_eq!(universe.distance(a, b), d);
+        }
+        let empty = Universe::new(&Image::from_str("..\n.#"), 2, 2);
+        assert_eq!(empty.closest_pair(), None);
+    }
+
+    #[test]
+    fn test_queries() {
+        let universe = Universe::new(&Image::from_str(EXAMPLE), 2, 2);
+        assert_eq!(
+            universe.nearest(0, 3).unwrap(),
+            vec![(1, 6), (2, 6), (3, 9)]
+        );
+        assert_eq!(universe.nearest(8, 100).unwrap().len(), 8);
+        assert!(universe.nearest(9, 1).is_err());
+
+        let histogram = universe.distance_histogram(5).unwrap();
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            vec![(5, 18), (10, 11), (15, 7)]
+        );
+        assert!(universe.distance_histogram(0).is_err());
+        assert!(universe.distance_histogram(-3).is_err());
+    }
+}
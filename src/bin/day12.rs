@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use anyhow::Error;
+use aoc2023::XorShift;
+use num::{BigUint, CheckedAdd, One, Zero};
+use std::cmp::min;
 use std::fs;
+use std::iter::{successors, zip};
+use std::ops::SubAssign;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 struct Row {
     condition: Vec<char>,
@@ -8,21 +15,22 @@ struct Row {
 }
 
 impl Row {
-    fn unfold(&self) -> Self {
+    fn unfold(&self, factor: usize, separator: char) -> Self {
         Self {
             condition: self
                 .condition
                 .iter()
-                .chain(['?'].iter())
+                .chain([separator].iter())
                 .cycle()
-                .take((self.condition.len() + 1) * 5 - 1)
+                // Zero copies leave an empty row
+                .take(((self.condition.len() + 1) * factor).saturating_sub(1))
                 .copied()
                 .collect(),
             groups: self
                 .groups
                 .iter()
                 .cycle()
-                .take(self.groups.len() * 5)
+                .take(self.groups.len() * factor)
                 .copied()
                 .collect(),
         }
@@ -44,81 +52,461 @@ fn parse_input(input: &str) -> Vec<Row> {
     input.lines().map(parse_line).collect()
 }
 
-#[derive(Eq, PartialEq, Hash, Debug)]
-struct SolveState {
-    pos: usize,
-    num_groups: usize,
-}
-
-fn solve_row(row: &Row) -> usize {
-    let mut states = HashMap::from([(
-        SolveState {
-            pos: 0,
-            num_groups: 0,
-        },
-        1_usize,
-    )]);
-    let num_total_groups = row.groups.len();
-
-    for p in 0..row.condition.len() {
-        if row.condition[p] != '#' {
-            for g in 0..num_total_groups + 1 {
-                if let Some(&num_perms) = states.get(&SolveState {
-                    pos: p,
-                    num_groups: g,
-                }) {
-                    states
-                        .entry(SolveState {
-                            pos: p + 1,
-                            num_groups: g,
-                        })
-                        .and_modify(|n| *n += num_perms)
-                        .or_insert(num_perms);
+trait Count:
+    Clone
+    + Ord
+    + Zero
+    + One
+    + CheckedAdd
+    + for<'b> SubAssign<&'b Self>
+    + Into<BigUint>
+    + FromStr<Err: std::error::Error + Send + Sync + 'static>
+{
+    fn random_below(bound: &Self, rng: &mut XorShift) -> Self;
+}
+
+// Both draw uniformly by rejecting values from the incomplete block at the top of the range
+impl Count for u128 {
+    fn random_below(bound: &Self, rng: &mut XorShift) -> Self {
+        let limit = u128::MAX - u128::MAX % bound;
+        loop {
+            let value = (rng.next_u64() as u128) << 64 | rng.next_u64() as u128;
+            if value < limit {
+                return value % bound;
+            }
+        }
+    }
+}
+
+impl Count for BigUint {
+    fn random_below(bound: &Self, rng: &mut XorShift) -> Self {
+        let bits = bound.bits();
+        let num_digits = bits.div_ceil(32);
+        loop {
+            let digits = (0..num_digits).map(|_| rng.next_u64() as u32).collect();
+            let value = BigUint::new(digits) >> (num_digits * 32 - bits);
+            if value < *bound {
+                return value;
+            }
+        }
+    }
+}
+
+struct ArrangementCounts<'a, T: Count> {
+    row: &'a Row,
+    // ways[p][g]: completions of condition[p..] with groups[g..]
+    ways: Vec<Vec<T>>,
+}
+
+impl<'a, T: Count> ArrangementCounts<'a, T> {
+    // None if the counts overflow T
+    fn new(row: &'a Row) -> Option<Self> {
+        let len = row.condition.len();
+        let num_groups = row.groups.len();
+        let mut ways = vec![vec![T::zero(); num_groups + 1]; len + 1];
+        ways[len][num_groups] = T::one();
+        for p in (0..len).rev() {
+            for g in 0..num_groups + 1 {
+                if row.condition[p] != '#' {
+                    ways[p][g] = ways[p][g].checked_add(&ways[p + 1][g])?;
+                }
+                if let Some(next) = Self::group_end(row, p, g) {
+                    ways[p][g] = ways[p][g].checked_add(&ways[next][g + 1])?;
                 }
             }
         }
+        Some(Self { row, ways })
+    }
 
-        for g in 0..num_total_groups {
-            let end_g = p + row.groups[g];
-            if end_g <= row.condition.len()
-                && !row.condition[p..end_g].contains(&'.')
-                && (end_g == row.condition.len() || row.condition[end_g] != '#')
-            {
-                if let Some(&num_perms) = states.get(&SolveState {
-                    pos: p,
-                    num_groups: g,
-                }) {
-                    states
-                        .entry(SolveState {
-                            pos: end_g + 1,
-                            num_groups: g + 1,
-                        })
-                        .and_modify(|n| *n += num_perms)
-                        .or_insert(num_perms);
+    // Position after group g and its separator, if the group can start at p
+    fn group_end(row: &Row, p: usize, g: usize) -> Option<usize> {
+        let end_g = p + row.groups.get(g)?;
+        if end_g <= row.condition.len()
+            && !row.condition[p..end_g].contains(&'.')
+            && (end_g == row.condition.len() || row.condition[end_g] != '#')
+        {
+            Some(min(end_g + 1, row.condition.len()))
+        } else {
+            None
+        }
+    }
+
+    fn total(&self) -> &T {
+        &self.ways[0][0]
+    }
+
+    // Arrangements are ordered lexicographically as strings, so '#' comes before '.'
+    fn kth(&self, mut k: T) -> Option<String> {
+        if k >= *self.total() {
+            return None;
+        }
+        let mut ret = String::new();
+        let (mut p, mut g) = (0, 0);
+        while p < self.row.condition.len() {
+            if let Some(next) = Self::group_end(self.row, p, g) {
+                let with_group = &self.ways[next][g + 1];
+                if k < *with_group {
+                    ret += &"#".repeat(self.row.groups[g]);
+                    if next > p + self.row.groups[g] {
+                        ret.push('.');
+                    }
+                    (p, g) = (next, g + 1);
+                    continue;
                 }
+                k -= with_group;
             }
+            ret.push('.');
+            p += 1;
         }
+        Some(ret)
     }
 
-    (row.condition.len()..row.condition.len() + 2)
-        .filter_map(|p| {
-            states.get(&SolveState {
-                pos: p,
-                num_groups: num_total_groups,
-            })
-        })
-        .sum()
+    fn sample(&self, rng: &mut XorShift) -> Option<String> {
+        if self.total().is_zero() {
+            return None;
+        }
+        self.kth(T::random_below(self.total(), rng))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = String> + '_ {
+        successors(Some(T::zero()), |k| k.checked_add(&T::one())).map_while(|k| self.kth(k))
+    }
+
+    // For every cell, whether some arrangement has it damaged and whether some has it operational
+    fn cell_options(&self) -> Vec<(bool, bool)> {
+        let len = self.row.condition.len();
+        let num_groups = self.row.groups.len();
+        let mut reachable = vec![vec![false; num_groups + 1]; len + 1];
+        reachable[0][0] = true;
+        let mut ret = vec![(false, false); len];
+        for p in 0..len {
+            for g in 0..num_groups + 1 {
+                if !reachable[p][g] {
+                    continue;
+                }
+                if self.row.condition[p] != '#' && !self.ways[p + 1][g].is_zero() {
+                    reachable[p + 1][g] = true;
+                    ret[p].1 = true;
+                }
+                if let Some(next) = Self::group_end(self.row, p, g) {
+                    if !self.ways[next][g + 1].is_zero() {
+                        reachable[next][g + 1] = true;
+                        let end_g = p + self.row.groups[g];
+                        ret[p..end_g].iter_mut().for_each(|o| o.0 = true);
+                        if end_g < next {
+                            ret[end_g].1 = true;
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+fn solve_row(row: &Row) -> BigUint {
+    match ArrangementCounts::<u128>::new(row) {
+        Some(counts) => (*counts.total()).into(),
+        None => ArrangementCounts::<BigUint>::new(row)
+            .unwrap()
+            .total()
+            .clone(),
+    }
 }
 
-fn solve(input: &[Row]) -> usize {
+fn solve(input: &[Row]) -> BigUint {
     input.iter().map(solve_row).sum()
 }
 
-fn main() {
-    let input = parse_input(&fs::read_to_string(Path::new("data/input12.txt")).unwrap());
+struct Nonogram {
+    row_clues: Vec<Vec<usize>>,
+    col_clues: Vec<Vec<usize>>,
+}
+
+enum NonogramSolution {
+    Unique(Vec<Vec<char>>),
+    Multiple(Vec<Vec<char>>, Vec<Vec<char>>),
+    Impossible,
+}
+
+impl Nonogram {
+    // Row clues, an empty line, then column clues; "0" marks an empty line
+    fn from_str(input: &str) -> Result<Self, Error> {
+        let parse_clues = |block: &str| -> Result<Vec<Vec<usize>>, Error> {
+            block
+                .lines()
+                .map(|l| {
+                    l.split(',')
+                        .map(|n| Ok(n.trim().parse::<usize>()?))
+                        .filter(|n| !matches!(n, Ok(0)))
+                        .collect()
+                })
+                .collect()
+        };
+        let (rows, cols) = input.split_once("\n\n").ok_or(Error::msg(
+            "Row and column clues must be separated by an empty line",
+        ))?;
+        Ok(Self {
+            row_clues: parse_clues(rows)?,
+            col_clues: parse_clues(cols)?,
+        })
+    }
+
+    // Returns false on a contradiction
+    fn solve_line(line: &mut [char], groups: &[usize]) -> bool {
+        let row = Row {
+            condition: line.to_vec(),
+            groups: groups.to_vec(),
+        };
+        let cell_options = match ArrangementCounts::<u128>::new(&row) {
+            Some(counts) => counts.cell_options(),
+            None => ArrangementCounts::<BigUint>::new(&row)
+                .unwrap()
+                .cell_options(),
+        };
+        for (cell, options) in zip(line.iter_mut(), cell_options) {
+            *cell = match options {
+                (true, true) => '?',
+                (true, false) => '#',
+                (false, true) => '.',
+                (false, false) => return false,
+            };
+        }
+        true
+    }
+
+    fn propagate(&self, grid: &mut [Vec<char>]) -> bool {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (row, groups) in zip(grid.iter_mut(), &self.row_clues) {
+                let before = row.clone();
+                if !Self::solve_line(row, groups) {
+                    return false;
+                }
+                changed |= *row != before;
+            }
+            for (c, groups) in self.col_clues.iter().enumerate() {
+                let mut col: Vec<char> = grid.iter().map(|r| r[c]).collect();
+                let before = col.clone();
+                if !Self::solve_line(&mut col, groups) {
+                    return false;
+                }
+                if col != before {
+                    changed = true;
+                    zip(grid.iter_mut(), col).for_each(|(r, cell)| r[c] = cell);
+                }
+            }
+        }
+        true
+    }
+
+    fn search(&self, mut grid: Vec<Vec<char>>, solutions: &mut Vec<Vec<Vec<char>>>) {
+        if solutions.len() >= 2 || !self.propagate(&mut grid) {
+            return;
+        }
+        let unknown = grid
+            .iter()
+            .enumerate()
+            .find_map(|(r, row)| row.iter().position(|&c| c == '?').map(|c| (r, c)));
+        match unknown {
+            None => solutions.push(grid),
+            Some((r, c)) => {
+                for guess in ['#', '.'] {
+                    let mut next = grid.clone();
+                    next[r][c] = guess;
+                    self.search(next, solutions);
+                }
+            }
+        }
+    }
+
+    fn solve(&self) -> NonogramSolution {
+        let mut solutions = vec![];
+        self.search(
+            vec![vec!['?'; self.col_clues.len()]; self.row_clues.len()],
+            &mut solutions,
+        );
+        let mut solutions = solutions.into_iter();
+        match (solutions.next(), solutions.next()) {
+            (Some(first), None) => NonogramSolution::Unique(first),
+            (Some(first), Some(second)) => NonogramSolution::Multiple(first, second),
+            _ => NonogramSolution::Impossible,
+        }
+    }
+}
+
+fn print_picture(grid: &[Vec<char>]) {
+    for row in grid {
+        println!("{}", row.iter().collect::<String>());
+    }
+}
+
+fn query<T: Count>(
+    counts: &ArrangementCounts<T>,
+    command: &str,
+    number: Option<&String>,
+) -> Result<(), Error> {
+    match command {
+        "list" => counts
+            .iter()
+            .take(number.map_or(Ok(10), |n| n.parse())?)
+            .for_each(|a| println!("{}", a)),
+        "kth" => println!(
+            "{:?}",
+            counts.kth(number.map_or(Ok(T::zero()), |n| n.parse())?)
+        ),
+        "sample" => {
+            let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+            let mut rng = XorShift::new(seed as u64);
+            for _ in 0..number.map_or(Ok(1), |n| n.parse())? {
+                println!("{:?}", counts.sample(&mut rng));
+            }
+        }
+        _ => return Err(Error::msg(format!("Unknown query {}", command))),
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let input = parse_input(&fs::read_to_string(Path::new("data/input12.txt"))?);
     println!("{}", solve(&input));
-    println!(
-        "{}",
-        solve(&input.iter().map(Row::unfold).collect::<Vec<Row>>())
-    );
+    let unfolded = input.iter().map(|r| r.unfold(5, '?')).collect::<Vec<Row>>();
+    println!("{}", solve(&unfolded));
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        return Ok(());
+    }
+    match args[0].as_str() {
+        "nonogram" => {
+            let nonogram = Nonogram::from_str(&fs::read_to_string(Path::new(&args[1]))?)?;
+            match nonogram.solve() {
+                NonogramSolution::Unique(grid) => print_picture(&grid),
+                NonogramSolution::Multiple(first, second) => {
+                    println!("Multiple solutions, e.g.");
+                    print_picture(&first);
+                    println!();
+                    print_picture(&second);
+                }
+                NonogramSolution::Impossible => println!("No solution"),
+            }
+        }
+        "unfold" => {
+            let factor = args[1].parse()?;
+            let separator = args.get(2).map_or('?', |s| s.chars().next().unwrap());
+            let rows: Vec<_> = input.iter().map(|r| r.unfold(factor, separator)).collect();
+            println!("{}", solve(&rows));
+        }
+        command => {
+            let rows = if args.last().unwrap() == "unfolded" {
+                &unfolded
+            } else {
+                &input
+            };
+            let row = rows
+                .get(args[1].parse::<usize>()?)
+                .ok_or(Error::msg("No such row"))?;
+            let number = args.get(2).filter(|n| *n != "unfolded");
+            match ArrangementCounts::<u128>::new(row) {
+                Some(counts) => query(&counts, command, number)?,
+                None => query(
+                    &ArrangementCounts::<BigUint>::new(row).unwrap(),
+                    command,
+                    number,
+                )?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(row: &Row, arrangement: &str) -> bool {
+        let groups: Vec<usize> = arrangement
+            .split('.')
+            .filter(|g| !g.is_empty())
+            .map(str::len)
+            .collect();
+        groups == row.groups
+            && zip(&row.condition, arrangement.chars()).all(|(&c, a)| c == '?' || c == a)
+    }
+
+    #[test]
+    fn test_arrangements() {
+        let row = parse_line("?###???????? 3,2,1");
+        let counts = ArrangementCounts::<u128>::new(&row).unwrap();
+        let arrangements: Vec<_> = counts.iter().collect();
+        assert_eq!(arrangements.len(), 10);
+        assert!(arrangements.windows(2).all(|w| w[0] < w[1]));
+        assert!(arrangements.iter().all(|a| matches(&row, a)));
+        assert_eq!(counts.kth(10), None);
+    }
+
+    #[test]
+    fn test_big_counts() {
+        let row = parse_line("?? 1").unfold(100, '?');
+        assert!(ArrangementCounts::<u128>::new(&row).is_none());
+        // 100 non-adjacent cells out of 299 can be chosen in C(200, 100) ways
+        let binomial = (1..=100_u32).fold(BigUint::one(), |acc, i| acc * (100 + i) / i);
+        assert_eq!(solve_row(&row), binomial);
+    }
+
+    #[test]
+    fn test_unfold() {
+        let row = parse_line("???.### 1,1,3");
+        assert_eq!(solve_row(&row.unfold(5, '?')), BigUint::from(1_u32));
+        let empty = row.unfold(0, '?');
+        assert!(empty.condition.is_empty() && empty.groups.is_empty());
+        assert_eq!(solve_row(&empty), BigUint::one());
+    }
+
+    #[test]
+    fn test_nonogram() {
+        let plus = Nonogram::from_str("1\n3\n1\n\n1\n3\n1").unwrap();
+        match plus.solve() {
+            NonogramSolution::Unique(grid) => assert_eq!(
+                grid,
+                vec![
+                    vec!['.', '#', '.'],
+                    vec!['#', '#', '#'],
+                    vec!['.', '#', '.']
+                ]
+            ),
+            _ => panic!("Unique solution expected"),
+        }
+        let diagonal = Nonogram::from_str("1\n1\n\n1\n1").unwrap();
+        assert!(matches!(diagonal.solve(), NonogramSolution::Multiple(_, _)));
+        let impossible = Nonogram::from_str("2\n\n1").unwrap();
+        assert!(matches!(impossible.solve(), NonogramSolution::Impossible));
+        assert!(Nonogram::from_str("1\n1").is_err());
+        assert!(Nonogram::from_str("1\nx\n\n1").is_err());
+    }
+
+    #[test]
+    fn test_random_below() {
+        let mut rng = XorShift::new(42);
+        let mut counts = [0; 6];
+        for _ in 0..6000 {
+            counts[u128::random_below(&6, &mut rng) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&c| (850..1150).contains(&c)));
+
+        let bound = BigUint::from(5_u32) << 100;
+        let mut top_fifth = 0;
+        for _ in 0..1000 {
+            let value = BigUint::random_below(&bound, &mut rng);
+            assert!(value < bound);
+            if value >= BigUint::from(4_u32) << 100 {
+                top_fifth += 1;
+            }
+        }
+        assert!((150..250).contains(&top_fifth));
+
+        let huge = (1_u128 << 127) + 1;
+        assert!((0..100).all(|_| u128::random_below(&huge, &mut rng) < huge));
+    }
 }
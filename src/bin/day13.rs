@@ -1,20 +1,46 @@
 use anyhow::Error;
+use aoc2023::XorShift;
 use std::fs;
 use std::iter::zip;
 use std::path::Path;
+use std::time::Instant;
+
+// One bit per cell, set for rocks, in 64-cell words
+type BitLine = Vec<u64>;
+
+fn count_diffs(a: &BitLine, b: &BitLine) -> usize {
+    zip(a, b).map(|(a, b)| (a ^ b).count_ones() as usize).sum()
+}
+
+fn diff_positions(a: &BitLine, b: &BitLine) -> Vec<usize> {
+    let mut ret = vec![];
+    for (word, (a, b)) in zip(a, b).enumerate() {
+        let mut diff = a ^ b;
+        while diff != 0 {
+            ret.push(word * 64 + diff.trailing_zeros() as usize);
+            diff &= diff - 1;
+        }
+    }
+    ret
+}
 
 struct Pattern {
-    rows: Vec<String>,
-    cols: Vec<String>,
+    rows: Vec<BitLine>,
+    cols: Vec<BitLine>,
 }
 
 impl Pattern {
     fn from_str(input: &str) -> Self {
-        let rows: Vec<String> = input.lines().map(|s| String::from(s.trim())).collect();
-        let mut cols = vec![String::new(); rows[0].len()];
-        for row in rows.iter() {
-            for (j, c) in row.chars().enumerate() {
-                cols[j].push(c)
+        let lines: Vec<&str> = input.lines().map(str::trim).collect();
+        let width = lines[0].len();
+        let mut rows = vec![vec![0_u64; width.div_ceil(64)]; lines.len()];
+        let mut cols = vec![vec![0_u64; lines.len().div_ceil(64)]; width];
+        for (i, line) in lines.iter().enumerate() {
+            for (j, c) in line.chars().enumerate() {
+                if c == '#' {
+                    rows[i][j / 64] |= 1 << (j % 64);
+                    cols[j][i / 64] |= 1 << (i % 64);
+                }
             }
         }
 
@@ -26,39 +52,265 @@ fn parse_input(input: &str) -> Vec<Pattern> {
     input.split("\n\n").map(Pattern::from_str).collect()
 }
 
-fn count_diffs(a: &str, b: &str) -> usize {
-    zip(a.chars(), b.chars())
-        .map(|(ac, bc)| if ac == bc { 0 } else { 1 })
-        .sum()
+#[derive(Copy, Clone, Debug)]
+enum Orientation {
+    Horizontal,
+    Vertical,
 }
 
-fn mirror_index(lines: &Vec<String>, num_diffs: usize) -> Option<usize> {
-    for i in 0..lines.len() - 1 {
-        if count_diffs(&lines[i], &lines[i + 1]) <= num_diffs
-            && zip(lines[0..i + 1].iter().rev(), lines[i + 1..].iter())
-                .map(|(lhs, rhs)| count_diffs(lhs, rhs))
-                .sum::<usize>()
-                == num_diffs
-        {
-            return Some(i + 1);
+#[derive(Debug)]
+struct Reflection {
+    orientation: Orientation,
+    index: usize,
+    // (row, col) of each mismatching cell on the top or left side of the line
+    mismatches: Vec<(usize, usize)>,
+}
+
+impl Orientation {
+    fn score(&self, index: usize) -> usize {
+        match self {
+            Orientation::Horizontal => 100 * index,
+            Orientation::Vertical => index,
+        }
+    }
+}
+
+impl Reflection {
+    fn smudge(&self) -> Option<(usize, usize)> {
+        match self.mismatches[..] {
+            [cell] => Some(cell),
+            _ => None,
         }
     }
-    None
 }
 
-fn solve(input: &[Pattern], num_diffs: usize) -> usize {
+fn mirrored_pairs(index: usize, len: usize) -> impl Iterator<Item = (usize, usize)> {
+    zip((0..index).rev(), index..len)
+}
+
+impl Pattern {
+    fn lines(&self) -> [(Orientation, &Vec<BitLine>); 2] {
+        [
+            (Orientation::Vertical, &self.cols),
+            (Orientation::Horizontal, &self.rows),
+        ]
+    }
+
+    fn reflections(&self) -> Vec<Reflection> {
+        let mut ret = vec![];
+        for (orientation, lines) in self.lines() {
+            for index in 1..lines.len() {
+                let mut mismatches = vec![];
+                for (a, b) in mirrored_pairs(index, lines.len()) {
+                    for pos in diff_positions(&lines[a], &lines[b]) {
+                        mismatches.push(match orientation {
+                            Orientation::Horizontal => (a, pos),
+                            Orientation::Vertical => (pos, a),
+                        });
+                    }
+                }
+                ret.push(Reflection {
+                    orientation,
+                    index,
+                    mismatches,
+                });
+            }
+        }
+        ret
+    }
+
+    fn score(&self, num_diffs: usize) -> Option<usize> {
+        for (orientation, lines) in self.lines() {
+            for index in 1..lines.len() {
+                let mut diffs = 0;
+                for (a, b) in mirrored_pairs(index, lines.len()) {
+                    diffs += count_diffs(&lines[a], &lines[b]);
+                    if diffs > num_diffs {
+                        break;
+                    }
+                }
+                if diffs == num_diffs {
+                    return Some(orientation.score(index));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn solve(input: &[Pattern], num_diffs: usize) -> Result<usize, Error> {
     input
         .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            p.score(num_diffs).ok_or(Error::msg(format!(
+                "Pattern {} has no line with {} mismatches",
+                i, num_diffs
+            )))
+        })
+        .sum()
+}
+
+// The character-by-character comparison, kept as a baseline for the stress test
+fn solve_with_strings(input: &str, num_diffs: usize) -> Option<usize> {
+    let count_diffs =
+        |a: &String, b: &String| zip(a.chars(), b.chars()).filter(|(a, b)| a != b).count();
+    let score = |lines: &Vec<String>| {
+        (1..lines.len()).find(|&index| {
+            mirrored_pairs(index, lines.len())
+                .map(|(a, b)| count_diffs(&lines[a], &lines[b]))
+                .sum::<usize>()
+                == num_diffs
+        })
+    };
+    input
+        .split("\n\n")
         .map(|p| {
-            mirror_index(&p.cols, num_diffs)
-                .unwrap_or_else(|| mirror_index(&p.rows, num_diffs).unwrap() * 100)
+            let rows: Vec<String> = p.lines().map(String::from).collect();
+            let cols: Vec<String> = (0..rows[0].len())
+                .map(|j| rows.iter().map(|r| r.as_bytes()[j] as char).collect())
+                .collect();
+            score(&cols).or_else(|| score(&rows).map(|i| 100 * i))
         })
         .sum()
 }
 
+// Random patterns with a planted reflection line and a single smudge
+fn generate_patterns(count: usize, size: usize, rng: &mut XorShift) -> String {
+    let mut patterns = vec![];
+    for _ in 0..count {
+        let mut grid: Vec<Vec<char>> = (0..size)
+            .map(|_| {
+                (0..size)
+                    .map(|_| if rng.next_u64() & 1 == 0 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+        let index = 1 + rng.next_u64() as usize % (size - 1);
+        for (a, b) in mirrored_pairs(index, size) {
+            let mirrored = grid[a].clone();
+            grid[b] = mirrored;
+        }
+        let (a, _) = mirrored_pairs(index, size).next().unwrap();
+        let col = rng.next_u64() as usize % size;
+        grid[a][col] = if grid[a][col] == '#' { '.' } else { '#' };
+        if rng.next_u64() & 1 == 0 {
+            grid = (0..size)
+                .map(|j| grid.iter().map(|r| r[j]).collect())
+                .collect();
+        }
+        patterns.push(
+            grid.iter()
+                .map(|r| r.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+    patterns.join("\n\n")
+}
+
+fn stress_test(count: usize, size: usize) -> Result<(), Error> {
+    if size < 2 {
+        return Err(Error::msg(
+            "Patterns need a size of at least 2 to hold a reflection",
+        ));
+    }
+    let raw_input = generate_patterns(count, size, &mut XorShift::new(13));
+
+    let start = Instant::now();
+    let with_strings = solve_with_strings(&raw_input, 1);
+    let string_time = start.elapsed();
+
+    let start = Instant::now();
+    let input = parse_input(&raw_input);
+    let with_bits = solve(&input, 1)?;
+    let bit_time = start.elapsed();
+
+    assert_eq!(with_strings, Some(with_bits));
+    println!(
+        "{} patterns of {}x{}: strings {:?}, bitmasks {:?} (including parsing)",
+        count, size, size, string_time, bit_time
+    );
+    Ok(())
+}
+
 fn main() -> Result<(), Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("stress") {
+        let count = args.get(1).map_or(Ok(5000), |n| n.parse())?;
+        let size = args.get(2).map_or(Ok(100), |n| n.parse())?;
+        return stress_test(count, size);
+    }
+
     let input = parse_input(&fs::read_to_string(Path::new("data/input13.txt"))?);
-    println!("{}", solve(&input, 0));
-    println!("{}", solve(&input, 1));
+    if args.iter().any(|arg| arg == "analyze") {
+        for (i, pattern) in input.iter().enumerate() {
+            println!("Pattern {}:", i);
+            for reflection in pattern.reflections() {
+                println!(
+                    "  {:?} {}: {} mismatches{}",
+                    reflection.orientation,
+                    reflection.index,
+                    reflection.mismatches.len(),
+                    reflection
+                        .smudge()
+                        .map_or(String::new(), |cell| format!(", smudge at {:?}", cell))
+                );
+            }
+        }
+    }
+    println!("{}", solve(&input, 0)?);
+    println!("{}", solve(&input, 1)?);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "#.##..##.\n..#.##.#.\n##......#\n##......#\n..#.##.#.\n..##..##.\n#.#.##.#.\n\n\
+                           #...##..#\n#....#..#\n..##..###\n#####.##.\n#####.##.\n..##..###\n#....#..#";
+
+    #[test]
+    fn test_example() {
+        let input = parse_input(EXAMPLE);
+        assert_eq!(solve(&input, 0).unwrap(), 405);
+        assert_eq!(solve(&input, 1).unwrap(), 400);
+        let smudges: Vec<_> = input
+            .iter()
+            .flat_map(|p| p.reflections().into_iter().filter_map(|r| r.smudge()))
+            .collect();
+        assert_eq!(smudges, vec![(0, 0), (0, 4)]);
+    }
+
+    #[test]
+    fn test_no_reflection() {
+        assert!(solve(&parse_input("#.\n.#"), 0).is_err());
+    }
+
+    #[test]
+    fn test_multi_word_lines() {
+        let raw_input = generate_patterns(5, 150, &mut XorShift::new(46));
+        let input = parse_input(&raw_input);
+        assert!(input
+            .iter()
+            .all(|p| p.rows[0].len() == 3 && p.cols[0].len() == 3));
+        assert_eq!(
+            Some(solve(&input, 1).unwrap()),
+            solve_with_strings(&raw_input, 1)
+        );
+    }
+
+    #[test]
+    fn test_generated_round_trip() {
+        let raw_input = generate_patterns(200, 8, &mut XorShift::new(7));
+        let input = parse_input(&raw_input);
+        assert_eq!(
+            Some(solve(&input, 1).unwrap()),
+            solve_with_strings(&raw_input, 1)
+        );
+        assert!(input
+            .iter()
+            .all(|p| p.reflections().iter().any(|r| r.smudge().is_some())));
+    }
+}
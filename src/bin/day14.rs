@@ -1,4 +1,5 @@
 use anyhow::Error;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 
@@ -18,126 +19,191 @@ impl Tile {
             _ => panic!("Not supposed to happen"),
         }
     }
-}
-
-fn parse_input(input: &str) -> Vec<Vec<Tile>> {
-    let lines: Vec<Vec<_>> = input
-        .lines()
-        .map(|l| l.chars().map(Tile::from_char).collect())
-        .collect();
 
-    let mut rows = vec![vec![Tile::Empty; lines.len()]; lines[0].len()];
-    for (i, line) in lines.iter().enumerate() {
-        for (j, tile) in line.iter().enumerate() {
-            rows[j][i] = *tile;
+    fn to_char(self) -> char {
+        match self {
+            Tile::Rounded => 'O',
+            Tile::Cube => '#',
+            Tile::Empty => '.',
         }
     }
-    rows
-}
-
-fn sub_load(offset: usize, num_rounded: usize, total: usize) -> usize {
-    if num_rounded > 0 {
-        let start_val = total - offset;
-        (start_val + start_val - num_rounded + 1) * num_rounded / 2
-    } else {
-        0
-    }
-}
 
-fn solve_col(col: &[Tile]) -> usize {
-    let mut num_rounded = 0;
-    let mut offset = 0;
-    let mut ret = 0;
-
-    for (index, tile) in col.iter().enumerate() {
-        match tile {
-            Tile::Rounded => num_rounded += 1,
-            Tile::Cube => {
-                if num_rounded != 0 {
-                    ret += sub_load(offset, num_rounded, col.len());
-                    num_rounded = 0;
-                }
-                offset = index + 1;
-            }
-            Tile::Empty => {}
+    fn rgb(self) -> [u8; 3] {
+        match self {
+            Tile::Rounded => [230, 230, 230],
+            Tile::Cube => [120, 60, 20],
+            Tile::Empty => [20, 20, 20],
         }
     }
-    if num_rounded != 0 {
-        ret += sub_load(offset, num_rounded, col.len());
-    }
+}
 
-    ret
+#[derive(Copy, Clone, Debug)]
+enum Direction {
+    North,
+    West,
+    South,
+    East,
 }
 
-fn score_col(col: &[Tile]) -> usize {
-    col.iter()
-        .enumerate()
-        .map(|(i, &tile)| {
-            if tile == Tile::Rounded {
-                col.len() - i
-            } else {
-                0
-            }
+impl Direction {
+    fn from_char(c: char) -> Result<Self, Error> {
+        Ok(match c {
+            'N' => Direction::North,
+            'W' => Direction::West,
+            'S' => Direction::South,
+            'E' => Direction::East,
+            _ => return Err(Error::msg(format!("Invalid direction {}", c))),
         })
-        .sum()
+    }
 }
 
-fn score(map: &[Vec<Tile>]) -> usize {
-    map.iter().map(|c| score_col(c)).sum()
+fn parse_program(program: &str) -> Result<Vec<Direction>, Error> {
+    program.chars().map(Direction::from_char).collect()
 }
-fn solve(cols: &[Vec<Tile>]) -> usize {
-    cols.iter().map(|c| solve_col(c)).sum()
+
+#[derive(Clone)]
+struct Platform {
+    rows: Vec<Vec<Tile>>,
 }
 
-fn roll_col(col: &mut [Tile]) {
-    let mut num_rounded = 0;
-    let mut offset = 0;
+impl Platform {
+    fn from_str(input: &str) -> Self {
+        Self {
+            rows: input
+                .lines()
+                .map(|l| l.chars().map(Tile::from_char).collect())
+                .collect(),
+        }
+    }
 
-    for index in 0..col.len() {
-        let tile = col[index];
-        match tile {
-            Tile::Rounded => {
-                num_rounded += 1;
-                col[index] = Tile::Empty;
+    fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn width(&self) -> usize {
+        self.rows[0].len()
+    }
+
+    // Every line along which rocks roll, starting at the edge they roll towards
+    fn lines(&self, direction: Direction) -> Vec<Vec<(usize, usize)>> {
+        let (height, width) = (self.height(), self.width());
+        match direction {
+            Direction::North => (0..width)
+                .map(|c| (0..height).map(|r| (r, c)).collect())
+                .collect(),
+            Direction::South => (0..width)
+                .map(|c| (0..height).rev().map(|r| (r, c)).collect())
+                .collect(),
+            Direction::West => (0..height)
+                .map(|r| (0..width).map(|c| (r, c)).collect())
+                .collect(),
+            Direction::East => (0..height)
+                .map(|r| (0..width).rev().map(|c| (r, c)).collect())
+                .collect(),
+        }
+    }
+
+    fn tilt(&mut self, direction: Direction) {
+        for line in self.lines(direction) {
+            let mut offset = 0;
+            for (index, &(r, c)) in line.iter().enumerate() {
+                match self.rows[r][c] {
+                    Tile::Rounded => {
+                        self.rows[r][c] = Tile::Empty;
+                        let (target_r, target_c) = line[offset];
+                        self.rows[target_r][target_c] = Tile::Rounded;
+                        offset += 1;
+                    }
+                    Tile::Cube => offset = index + 1,
+                    Tile::Empty => {}
+                }
             }
-            Tile::Cube => {
-                if num_rounded != 0 {
-                    col[offset..offset + num_rounded].fill(Tile::Rounded);
-                    num_rounded = 0;
+        }
+    }
+
+    fn spin(&mut self, program: &[Direction]) {
+        for &direction in program {
+            self.tilt(direction);
+        }
+    }
+
+    fn load(&self, side: Direction) -> usize {
+        let (height, width) = (self.height(), self.width());
+        let mut ret = 0;
+        for (r, row) in self.rows.iter().enumerate() {
+            for (c, &tile) in row.iter().enumerate() {
+                if tile == Tile::Rounded {
+                    ret += match side {
+                        Direction::North => height - r,
+                        Direction::South => r + 1,
+                        Direction::West => width - c,
+                        Direction::East => c + 1,
+                    };
                 }
-                offset = index + 1;
             }
-            Tile::Empty => {}
         }
+        ret
     }
-    if num_rounded != 0 {
-        col[offset..offset + num_rounded].fill(Tile::Rounded);
+
+    fn to_ppm(&self, scale: usize) -> Vec<u8> {
+        let mut ret = format!(
+            "P6\n{} {}\n255\n",
+            self.width() * scale,
+            self.height() * scale
+        )
+        .into_bytes();
+        for row in &self.rows {
+            for _ in 0..scale {
+                for tile in row {
+                    for _ in 0..scale {
+                        ret.extend(tile.rgb());
+                    }
+                }
+            }
+        }
+        ret
     }
 }
 
-fn roll(map: &mut [Vec<Tile>]) {
-    for col in map.iter_mut() {
-        roll_col(col);
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.rows {
+            writeln!(f, "{}", row.iter().map(|t| t.to_char()).collect::<String>())?;
+        }
+        Ok(())
     }
 }
-fn rotate(map: &mut [Vec<Tile>]) {
-    let n = map.len();
-    for r in 0..n / 2 {
-        for c in 0..n / 2 {
-            let backup_tile = map[r][c];
-            map[r][c] = map[c][n - 1 - r];
-            map[c][n - 1 - r] = map[n - 1 - r][n - 1 - c];
-            map[n - 1 - r][n - 1 - c] = map[n - 1 - c][r];
-            map[n - 1 - c][r] = backup_tile;
+
+// The platform after every tilt, or only after every full spin, with a label for each frame
+fn frames(
+    platform: &Platform,
+    program: &[Direction],
+    num_spins: usize,
+    every_tilt: bool,
+) -> Vec<(String, Platform)> {
+    let mut platform = platform.clone();
+    let mut ret = vec![(String::from("Start"), platform.clone())];
+    for spin in 1..num_spins + 1 {
+        for (step, &direction) in program.iter().enumerate() {
+            platform.tilt(direction);
+            if every_tilt {
+                ret.push((
+                    format!("Spin {} tilt {} {:?}", spin, step + 1, direction),
+                    platform.clone(),
+                ));
+            }
+        }
+        if !every_tilt {
+            ret.push((format!("Spin {}", spin), platform.clone()));
         }
     }
+    ret
 }
 
-fn cycle(map: &mut [Vec<Tile>]) {
-    for _ in 0..4 {
-        roll(map);
-        rotate(map);
-    }
+fn solve(platform: &Platform) -> usize {
+    let mut platform = platform.clone();
+    platform.tilt(Direction::North);
+    platform.load(Direction::North)
 }
 
 fn find_period(vals: &[usize]) -> usize {
@@ -150,21 +216,155 @@ fn find_period(vals: &[usize]) -> usize {
     ret
 }
 
-fn solve2(map: &mut [Vec<Tile>]) -> usize {
-    let mut loads = vec![0];
+fn spin_loads(platform: &Platform, program: &[Direction], side: Direction) -> Vec<usize> {
+    let mut platform = platform.clone();
+    let mut loads = vec![platform.load(side)];
     for _ in 0..400 {
-        cycle(map);
-        loads.push(score(map));
+        platform.spin(program);
+        loads.push(platform.load(side));
     }
+    loads
+}
 
+fn solve2(platform: &Platform, program: &[Direction], side: Direction) -> usize {
+    let loads = spin_loads(platform, program, side);
     let period = find_period(&loads);
     loads[(1000000000 % period) + (200 / period) * period]
 }
 
 fn main() -> Result<(), Error> {
-    let mut cols = parse_input(&fs::read_to_string(Path::new("data/input14.txt"))?);
-    roll(&mut cols);
-    println!("{}", solve(&cols));
-    println!("{}", solve2(&mut cols));
+    let platform = Platform::from_str(&fs::read_to_string(Path::new("data/input14.txt"))?);
+    println!("{}", solve(&platform));
+    println!(
+        "{}",
+        solve2(&platform, &parse_program("NWSE")?, Direction::North)
+    );
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let frame_program = || parse_program(args.get(1).map_or("NWSE", String::as_str));
+    let num_spins = || args.get(2).map_or(Ok(10), |n| n.parse::<usize>());
+    // frames and ppm both take "spins" (the default) or "tilts" to pick one frame per step
+    let every_tilt = || match args.get(3).map(String::as_str) {
+        None | Some("spins") => Ok(false),
+        Some("tilts") => Ok(true),
+        Some(other) => Err(Error::msg(format!(
+            "Expected spins or tilts, got {}",
+            other
+        ))),
+    };
+    match args.first().map(String::as_str) {
+        Some("frames") => {
+            let period = find_period(&spin_loads(&platform, &frame_program()?, Direction::North));
+            println!("Detected period: {}", period);
+            for (label, frame) in frames(&platform, &frame_program()?, num_spins()?, every_tilt()?)
+            {
+                println!("{}: north load {}", label, frame.load(Direction::North));
+                println!("{}", frame);
+            }
+        }
+        Some("ppm") => {
+            let prefix = args.get(4).map_or("frame", String::as_str);
+            let frames = frames(&platform, &frame_program()?, num_spins()?, every_tilt()?);
+            for (i, (label, frame)) in frames.iter().enumerate() {
+                let file_name = format!("{}_{:04}.ppm", prefix, i);
+                fs::write(Path::new(&file_name), frame.to_ppm(4))?;
+                println!(
+                    "{}: {} (north load {})",
+                    file_name,
+                    label,
+                    frame.load(Direction::North)
+                );
+            }
+        }
+        // A spin program, optionally followed by the side to measure the load on
+        Some(program) => {
+            let side = args
+                .get(1)
+                .and_then(|s| s.chars().next())
+                .map_or(Ok(Direction::North), Direction::from_char)?;
+            println!("{}", solve2(&platform, &parse_program(program)?, side));
+        }
+        None => {}
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        let platform = Platform::from_str(
+            "O....#....\nO.OO#....#\n.....##...\nOO.#O....O\n.O.....O#.\n\
+             O.#..O.#.#\n..O..#O..O\n.......O..\n#....###..\n#OO..#....",
+        );
+        assert_eq!(solve(&platform), 136);
+        assert_eq!(
+            solve2(&platform, &parse_program("NWSE").unwrap(), Direction::North),
+            64
+        );
+    }
+
+    #[test]
+    fn test_rectangular_tilts() {
+        let platform = Platform::from_str("O.#..O\n.O..O#\nO..O..");
+        let expected = [
+            ("N", "OO#OOO\nO....#\n......\n", [17, 23, 7, 19]),
+            ("W", "O.#O..\nOO...#\nOO....\n", [12, 31, 12, 11]),
+            ("S", "..#..O\nO....#\nOO.OO.\n", [9, 23, 15, 19]),
+            ("E", ".O#..O\n...OO#\n....OO\n", [12, 14, 12, 28]),
+        ];
+        for (program, grid, loads) in expected {
+            let mut tilted = platform.clone();
+            tilted.spin(&parse_program(program).unwrap());
+            assert_eq!(tilted.to_string(), grid);
+            let sides = parse_program("NWSE").unwrap();
+            let side_loads: Vec<_> = sides.iter().map(|&side| tilted.load(side)).collect();
+            assert_eq!(side_loads, loads);
+        }
+
+        let mut spun = platform.clone();
+        spun.spin(&parse_program("NE").unwrap());
+        assert_eq!(spun.to_string(), "OO#OOO\n....O#\n......\n");
+        assert!(parse_program("NX").is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let platform = Platform::from_str("O.#..O\n.O..O#\nO..O..");
+        let program = parse_program("NE").unwrap();
+
+        let per_spin = frames(&platform, &program, 2, false);
+        let labels: Vec<_> = per_spin.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(labels, vec!["Start", "Spin 1", "Spin 2"]);
+        assert_eq!(per_spin[0].1.to_string(), platform.to_string());
+
+        let per_tilt = frames(&platform, &program, 2, true);
+        let labels: Vec<_> = per_tilt.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Start",
+                "Spin 1 tilt 1 North",
+                "Spin 1 tilt 2 East",
+                "Spin 2 tilt 1 North",
+                "Spin 2 tilt 2 East"
+            ]
+        );
+        assert_eq!(per_tilt[1].1.to_string(), "OO#OOO\nO....#\n......\n");
+        assert_eq!(per_tilt[2].1.to_string(), per_spin[1].1.to_string());
+    }
+
+    #[test]
+    fn test_ppm() {
+        let platform = Platform::from_str("O.#..O\n.O..O#\nO..O..");
+        let ppm = platform.to_ppm(4);
+        let header = b"P6\n24 12\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + 24 * 12 * 3);
+        assert_eq!(ppm[header.len()..header.len() + 3], Tile::Rounded.rgb());
+        let cube = header.len() + 2 * 4 * 3;
+        assert_eq!(ppm[cube..cube + 3], Tile::Cube.rgb());
+    }
+}
@@ -1,7 +1,8 @@
-use lazy_static::lazy_static;
-use regex::Regex;
+use anyhow::Error;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::iter::zip;
 use std::path::Path;
 
 #[derive(Debug, Clone)]
@@ -15,50 +16,74 @@ fn hash(step: &str) -> usize {
         .fold(0, |ret, c| ((ret + (c as usize)) * 17) % 256)
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 enum Action {
     Remove,
     Update(usize),
 }
 
+#[derive(Debug)]
 struct Instruction {
     label: String,
     action: Action,
 }
 
 impl Instruction {
-    fn from_str(string: &str) -> Self {
-        lazy_static! {
-            static ref RE: Regex = Regex::new(r"([a-z]+)(-|=)(\d*)").unwrap();
-        }
-        let groups = RE.captures(string).unwrap();
-        Self {
-            label: String::from(groups.get(1).unwrap().as_str()),
-            action: if groups.get(2).unwrap().as_str() == "-" {
-                Action::Remove
-            } else {
-                Action::Update(groups.get(3).unwrap().as_str().parse().unwrap())
-            },
+    fn from_str(string: &str) -> Result<Self, Error> {
+        let (label, action) = match string.strip_suffix('-') {
+            Some(label) => (label, Action::Remove),
+            None => {
+                let (label, focal_length) = string
+                    .rsplit_once('=')
+                    .ok_or(Error::msg("Expected '-' or '=' operation"))?;
+                (label, Action::Update(focal_length.parse()?))
+            }
+        };
+        if label.is_empty() {
+            return Err(Error::msg("Empty label"));
         }
+        Ok(Self {
+            label: String::from(label),
+            action,
+        })
     }
 }
 
-fn solve(sequence: &[&str]) -> usize {
-    sequence.iter().map(|&s| hash(s)).sum()
+// Newlines are ignored anywhere in the initialization sequence
+fn split_sequence(input: &str) -> Vec<String> {
+    input
+        .replace(['\n', '\r'], "")
+        .split(',')
+        .map(String::from)
+        .collect()
 }
 
-fn solve2(sequence: &[&str]) -> usize {
-    let instructions = sequence.iter().map(|&s| Instruction::from_str(s));
+fn parse_instructions(sequence: &[String]) -> Result<Vec<Instruction>, Error> {
+    sequence
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            Instruction::from_str(step)
+                .map_err(|e| Error::msg(format!("Invalid step {} \"{}\": {}", i, step, e)))
+        })
+        .collect()
+}
+
+fn solve(sequence: &[String]) -> usize {
+    sequence.iter().map(|s| hash(s)).sum()
+}
+
+fn solve2(instructions: &[Instruction]) -> usize {
     let mut lens_by_label: HashMap<String, LensInfo> = HashMap::new();
 
-    for (index, instruction) in instructions.enumerate() {
+    for (index, instruction) in instructions.iter().enumerate() {
         match instruction.action {
             Action::Remove => {
                 lens_by_label.remove(&instruction.label);
             }
             Action::Update(fl) => {
                 lens_by_label
-                    .entry(instruction.label)
+                    .entry(instruction.label.clone())
                     .and_modify(|e| {
                         e.focal_length = fl;
                     })
@@ -90,12 +115,107 @@ fn solve2(sequence: &[&str]) -> usize {
         .sum()
 }
 
-fn main() {
-    let raw_input = fs::read_to_string(Path::new("data/input15.txt")).unwrap();
-    let input: Vec<_> = raw_input.trim().split(',').collect();
+struct Boxes {
+    boxes: Vec<Vec<(String, usize)>>,
+}
+
+impl Boxes {
+    fn new() -> Self {
+        Self {
+            boxes: vec![vec![]; 256],
+        }
+    }
+
+    fn apply(&mut self, instruction: &Instruction) {
+        let lenses = &mut self.boxes[hash(&instruction.label)];
+        let slot = lenses.iter().position(|(l, _)| *l == instruction.label);
+        match (instruction.action, slot) {
+            (Action::Remove, Some(slot)) => {
+                lenses.remove(slot);
+            }
+            (Action::Remove, None) => {}
+            (Action::Update(fl), Some(slot)) => lenses[slot].1 = fl,
+            (Action::Update(fl), None) => lenses.push((instruction.label.clone(), fl)),
+        }
+    }
+
+    fn locate(&self, label: &str) -> Option<(usize, usize)> {
+        let box_ind = hash(label);
+        self.boxes[box_ind]
+            .iter()
+            .position(|(l, _)| l == label)
+            .map(|slot| (box_ind, slot))
+    }
+
+    fn focusing_power(&self) -> usize {
+        self.boxes
+            .iter()
+            .enumerate()
+            .map(|(box_ind, lenses)| -> usize {
+                lenses
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, (_, focal_length))| (1 + box_ind) * (1 + slot) * focal_length)
+                    .sum()
+            })
+            .sum()
+    }
+}
+
+impl fmt::Display for Boxes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (box_ind, lenses) in self.boxes.iter().enumerate() {
+            if !lenses.is_empty() {
+                let contents: Vec<_> = lenses
+                    .iter()
+                    .map(|(label, fl)| format!("[{} {}]", label, fl))
+                    .collect();
+                writeln!(f, "Box {}: {}", box_ind, contents.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
 
-    println!("{}", solve(&input));
+fn simulate(instructions: &[Instruction], num_steps: usize) -> Boxes {
+    let mut boxes = Boxes::new();
+    for instruction in instructions.iter().take(num_steps) {
+        boxes.apply(instruction);
+    }
+    boxes
+}
+
+fn main() -> Result<(), Error> {
+    let sequence = split_sequence(fs::read_to_string(Path::new("data/input15.txt"))?.trim());
+    let input = parse_instructions(&sequence)?;
+
+    println!("{}", solve(&sequence));
     println!("{}", solve2(&input));
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("trace") => {
+            let mut boxes = Boxes::new();
+            for (step, instruction) in zip(&sequence, &input) {
+                boxes.apply(instruction);
+                println!("After \"{}\":\n{}", step, boxes);
+            }
+        }
+        Some("locate") => {
+            let label = args.get(1).ok_or(Error::msg("Label expected"))?;
+            let num_steps = args.get(2).map_or(Ok(input.len()), |k| k.parse())?;
+            match simulate(&input, num_steps).locate(label) {
+                Some((box_ind, slot)) => println!("{}: box {}, slot {}", label, box_ind, slot),
+                None => println!("{}: not in any box", label),
+            }
+        }
+        Some("verify") => {
+            let simulated = simulate(&input, input.len()).focusing_power();
+            println!("simulation: {}, shortcut: {}", simulated, solve2(&input));
+        }
+        _ => {}
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -105,4 +225,34 @@ mod tests {
     fn test_hash() {
         assert_eq!(hash("HASH"), 52);
     }
+
+    const EXAMPLE: &str = "rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7";
+
+    #[test]
+    fn test_simulation_matches_shortcut() {
+        let sequence = split_sequence(EXAMPLE);
+        assert_eq!(solve(&sequence), 1320);
+        let instructions = parse_instructions(&sequence).unwrap();
+        let boxes = simulate(&instructions, instructions.len());
+        assert_eq!(boxes.focusing_power(), 145);
+        assert_eq!(solve2(&instructions), 145);
+        assert_eq!(boxes.locate("ot"), Some((3, 0)));
+        assert_eq!(boxes.locate("pc"), Some((3, 2)));
+        assert_eq!(simulate(&instructions, 4).locate("qp"), Some((1, 0)));
+        assert_eq!(simulate(&instructions, 5).locate("qp"), None);
+    }
+
+    #[test]
+    fn test_parsing() {
+        let sequence = split_sequence("Ab_9=4,x-y-,r\nn=1,a=b=2");
+        let instructions = parse_instructions(&sequence).unwrap();
+        let labels: Vec<_> = instructions.iter().map(|i| i.label.as_str()).collect();
+        assert_eq!(labels, vec!["Ab_9", "x-y", "rn", "a=b"]);
+        assert_eq!(hash(&sequence[2]), hash("rn=1"));
+
+        let error = parse_instructions(&split_sequence("rn=1,cm,-")).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid step 1 \"cm\""));
+        assert!(parse_instructions(&split_sequence("-")).is_err());
+        assert!(parse_instructions(&split_sequence("ab=x")).is_err());
+    }
 }
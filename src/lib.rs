@@ -7,3 +7,18 @@ pub fn parse_numbers(string: &str) -> Result<Vec<i64>, Error> {
         .map(|d| d.parse::<i64>())
         .collect::<Result<Vec<_>, _>>()?)
 }
+
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}